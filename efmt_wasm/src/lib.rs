@@ -25,6 +25,137 @@ pub fn format(text: *const u8, text_len: i32) -> *mut Vec<u8> {
     Box::into_raw(Box::new(formatted.into_bytes()))
 }
 
+/// Options given to [format_with_options()] as a JSON object.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FormatOptions {
+    print_width: Option<usize>,
+    default_off: bool,
+
+    /// Macro name to value (`null` means `true`).
+    predefined_macros: std::collections::BTreeMap<String, Option<String>>,
+
+    /// Include file path to content.
+    include_files: std::collections::BTreeMap<String, String>,
+}
+
+impl FormatOptions {
+    fn to_efmt_options(&self) -> efmt::Options {
+        let mut options = efmt::Options::default();
+        if let Some(n) = self.print_width {
+            options = options.max_columns(n);
+        }
+        if self.default_off {
+            options = options.default_off();
+        }
+        for (name, value) in &self.predefined_macros {
+            options = options.define_macro(name, value.as_deref());
+        }
+        if self.include_files.is_empty() {
+            return options.disable_include();
+        }
+        let mut source = efmt::parse::MemoryIncludeSource::new();
+        for (path, text) in &self.include_files {
+            source.insert(path, text);
+        }
+        options.include_source(source)
+    }
+}
+
+/// The result of [format_with_options()] serialized as a JSON object.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum FormatResult {
+    Ok { formatted: String, changed: bool },
+    Error(FormatError),
+}
+
+#[derive(Debug, serde::Serialize)]
+struct FormatError {
+    message: String,
+    reason: String,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+impl FormatError {
+    fn new(message: String) -> Self {
+        Self {
+            reason: message.clone(),
+            message,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+impl From<efmt::Error> for FormatError {
+    fn from(e: efmt::Error) -> Self {
+        Self {
+            message: e.message(),
+            reason: e.reason(),
+            line: e.line(),
+            column: e.column(),
+        }
+    }
+}
+
+/// Formats a text with the options given as a JSON object.
+///
+/// Unlike [format()], this function returns a JSON object that has either the formatted text or the error information.
+/// A panic during formatting is also reported as an error if the module is built with `panic = "unwind"`
+/// (with `panic = "abort"`, the default for WebAssembly targets, it still aborts).
+#[no_mangle]
+pub fn format_with_options(
+    text: *const u8,
+    text_len: i32,
+    options: *const u8,
+    options_len: i32,
+) -> *mut Vec<u8> {
+    let text = unsafe { std::slice::from_raw_parts(text, text_len as usize) };
+    let options = unsafe { std::slice::from_raw_parts(options, options_len as usize) };
+    let result = std::panic::catch_unwind(|| do_format_with_options(text, options))
+        .unwrap_or_else(|e| Err(FormatError::new(panic_message(e.as_ref()))));
+    let result = match result {
+        Ok(formatted) => FormatResult::Ok {
+            changed: formatted.as_bytes() != text,
+            formatted,
+        },
+        Err(e) => FormatResult::Error(e),
+    };
+    let json = serde_json::to_vec(&result).unwrap_or_else(|e| {
+        serde_json::json!({"status": "error", "message": e.to_string()})
+            .to_string()
+            .into_bytes()
+    });
+    Box::into_raw(Box::new(json))
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    let message = if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.as_str()
+    } else {
+        "unknown panic"
+    };
+    format!("Formatter panicked: {message}")
+}
+
+fn do_format_with_options(text: &[u8], options: &[u8]) -> Result<String, FormatError> {
+    let text = std::str::from_utf8(text).map_err(|e| FormatError::new(e.to_string()))?;
+    let options: FormatOptions = if options.is_empty() {
+        FormatOptions::default()
+    } else {
+        serde_json::from_slice(options)
+            .map_err(|e| FormatError::new(format!("Invalid options: {e}")))?
+    };
+    let formatted = options
+        .to_efmt_options()
+        .format_text::<efmt::items::ModuleOrConfig>(text)?;
+    Ok(formatted)
+}
+
 #[no_mangle]
 pub fn vec_offset(v: *mut Vec<u8>) -> *mut u8 {
     unsafe { &mut *v }.as_mut_ptr()
@@ -44,3 +175,83 @@ pub fn allocate_vec(len: i32) -> *mut Vec<u8> {
 pub fn free_vec(v: *mut Vec<u8>) {
     let _ = unsafe { Box::from_raw(v) };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call_format_with_options(text: &str, options: &str) -> serde_json::Value {
+        let result = format_with_options(
+            text.as_ptr(),
+            text.len() as i32,
+            options.as_ptr(),
+            options.len() as i32,
+        );
+        let result = unsafe { Box::from_raw(result) };
+        serde_json::from_slice(&result).expect("invalid JSON result")
+    }
+
+    #[test]
+    fn format_with_options_works() {
+        let result = call_format_with_options("foo()->bar.\n", "");
+        assert_eq!(
+            result,
+            serde_json::json!({"status": "ok", "formatted": "foo() ->\n    bar.\n", "changed": true})
+        );
+
+        let result = call_format_with_options("foo() ->\n    bar.\n", "");
+        assert_eq!(result["changed"], false);
+    }
+
+    #[test]
+    fn format_with_options_reports_errors() {
+        let result = call_format_with_options("foo() ->\n    bar(.\n", "");
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["line"], 2);
+        assert!(result["column"].is_u64());
+        assert!(result["message"].is_string());
+        assert!(result["reason"].is_string());
+
+        let result = call_format_with_options("foo() -> bar.\n", r#"{"unknown": 1}"#);
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["line"], serde_json::Value::Null);
+        assert!(result["message"]
+            .as_str()
+            .is_some_and(|m| m.starts_with("Invalid options:")));
+    }
+
+    #[test]
+    fn format_options_deserialization_works() {
+        let options: FormatOptions = serde_json::from_str(
+            r#"{
+              "print_width": 20,
+              "default_off": true,
+              "predefined_macros": {"DEBUG": null, "VSN": "\"1.0\""},
+              "include_files": {"include/foo.hrl": "-define(FOO, foo)."}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(options.print_width, Some(20));
+        assert!(options.default_off);
+        assert_eq!(options.predefined_macros["DEBUG"], None);
+        assert_eq!(options.predefined_macros["VSN"].as_deref(), Some("\"1.0\""));
+        assert_eq!(
+            options.include_files["include/foo.hrl"],
+            "-define(FOO, foo)."
+        );
+
+        // The options are applied to formatting.
+        let text = "-include(\"include/foo.hrl\").\n\nfoo() -> {?FOO, ?VSN}.\n";
+        let options = r#"{
+          "print_width": 20,
+          "predefined_macros": {"VSN": "\"1.0\""},
+          "include_files": {"include/foo.hrl": "-define(FOO, foo)."}
+        }"#;
+        let result = call_format_with_options(text, options);
+        assert_eq!(result["status"], "ok");
+        assert_eq!(
+            result["formatted"],
+            "-include(\"include/foo.hrl\").\n\n\nfoo() ->\n    {?FOO, ?VSN}.\n"
+        );
+    }
+}
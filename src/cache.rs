@@ -0,0 +1,240 @@
+//! Maintenance of a cache directory (`.efmt/cache/` by default).
+//!
+//! The directory contains the include cache (`VERSION/`) that holds the macro definitions collected from include files
+//! and the format cache (`formatted/VERSION/`) that records the files formatted correctly.
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The number of entries and the total size of a cache directory.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub include_entries: usize,
+    pub include_bytes: u64,
+    pub format_entries: usize,
+    pub format_bytes: u64,
+}
+
+impl CacheStats {
+    fn add(&mut self, entry: &Entry) {
+        match entry.kind {
+            EntryKind::Include => {
+                self.include_entries += 1;
+                self.include_bytes += entry.size;
+            }
+            EntryKind::Format => {
+                self.format_entries += 1;
+                self.format_bytes += entry.size;
+            }
+        }
+    }
+}
+
+/// Returns the statistics of the cache entries in `cache_dir`.
+pub fn stats<P: AsRef<Path>>(cache_dir: P) -> CacheStats {
+    let mut stats = CacheStats::default();
+    for entry in entries(cache_dir.as_ref()) {
+        stats.add(&entry);
+    }
+    stats
+}
+
+/// Removes all the cache entries in `cache_dir` and returns the statistics of the removed ones.
+pub fn clear<P: AsRef<Path>>(cache_dir: P) -> std::io::Result<CacheStats> {
+    remove_entries_if(cache_dir.as_ref(), |_| true)
+}
+
+/// Removes the cache entries that are no longer valid and returns the statistics of the removed ones.
+///
+/// An entry is regarded as invalid if it was created by another version of efmt,
+/// or it refers to a file that has been removed or modified since the entry was created.
+/// If `max_age` is specified, the entries older than it are also removed.
+pub fn prune<P: AsRef<Path>>(
+    cache_dir: P,
+    max_age: Option<Duration>,
+) -> std::io::Result<CacheStats> {
+    let now = SystemTime::now();
+    remove_entries_if(cache_dir.as_ref(), |entry| {
+        let expired = max_age.is_some_and(|max_age| {
+            now.duration_since(entry.mtime)
+                .is_ok_and(|age| age > max_age)
+        });
+        expired || !entry.is_current_version || entry.is_stale()
+    })
+}
+
+/// Removes the oldest entries in an include cache directory until the total size becomes `max_bytes` or less.
+pub(crate) fn enforce_max_size(dir: &Path, max_bytes: u64) {
+    let mut entries = version_dir_entries(dir, EntryKind::Include, true);
+    let mut total = entries.iter().map(|x| x.size).sum::<u64>();
+    if total <= max_bytes {
+        return;
+    }
+
+    entries.sort_by_key(|x| x.mtime);
+    for entry in entries {
+        if total <= max_bytes {
+            break;
+        }
+        match std::fs::remove_file(&entry.path) {
+            Ok(()) => {
+                log::debug!(
+                    "Removed the include cache entry {:?} to keep the cache size within {} bytes.",
+                    entry.path,
+                    max_bytes
+                );
+                total -= entry.size;
+            }
+            Err(e) => {
+                log::warn!(
+                    "Failed to remove the include cache entry {:?}: {}",
+                    entry.path,
+                    e
+                );
+            }
+        }
+    }
+}
+
+fn remove_entries_if(cache_dir: &Path, f: impl Fn(&Entry) -> bool) -> std::io::Result<CacheStats> {
+    let mut stats = CacheStats::default();
+    for entry in entries(cache_dir).into_iter().filter(|x| f(x)) {
+        match std::fs::remove_file(&entry.path) {
+            Ok(()) => stats.add(&entry),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(stats)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum EntryKind {
+    Include,
+    Format,
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: EntryKind,
+    path: PathBuf,
+    size: u64,
+    mtime: SystemTime,
+    is_current_version: bool,
+}
+
+impl Entry {
+    fn is_stale(&self) -> bool {
+        let Ok(data) = std::fs::read_to_string(&self.path) else {
+            return true;
+        };
+        match self.kind {
+            EntryKind::Include => serde_json::from_str::<crate::parse::include::CacheEntry>(&data)
+                .map_or(true, |x| x.is_stale()),
+            EntryKind::Format => serde_json::from_str::<crate::format_cache::CacheEntry>(&data)
+                .map_or(true, |x| x.is_stale()),
+        }
+    }
+}
+
+fn entries(cache_dir: &Path) -> Vec<Entry> {
+    let mut entries = Vec::new();
+    for (kind, dir, current_version) in [
+        (
+            EntryKind::Include,
+            cache_dir.to_path_buf(),
+            crate::parse::include::CACHE_FORMAT_VERISON,
+        ),
+        (
+            EntryKind::Format,
+            cache_dir.join(crate::format_cache::CACHE_DIR_NAME),
+            crate::format_cache::CACHE_FORMAT_VERISON,
+        ),
+    ] {
+        let Ok(version_dirs) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for version_dir in version_dirs.filter_map(|x| x.ok()) {
+            let name = version_dir.file_name();
+            let Some(version) = name.to_str() else {
+                continue;
+            };
+            let is_version = version
+                .strip_prefix('v')
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+            if !is_version {
+                continue;
+            }
+            entries.extend(version_dir_entries(
+                &version_dir.path(),
+                kind,
+                version == current_version,
+            ));
+        }
+    }
+    entries
+}
+
+fn version_dir_entries(dir: &Path, kind: EntryKind, is_current_version: bool) -> Vec<Entry> {
+    let Ok(files) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    files
+        .filter_map(|x| x.ok())
+        .filter_map(|file| {
+            let metadata = file.metadata().ok()?;
+            if !metadata.is_file() {
+                return None;
+            }
+            Some(Entry {
+                kind,
+                path: file.path(),
+                size: metadata.len(),
+                mtime: metadata.modified().ok()?,
+                is_current_version,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_and_clear_work() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache_dir = dir.path().join("cache");
+        let header = dir.path().join("foo.hrl");
+        let file = dir.path().join("foo.erl");
+        std::fs::write(&header, "-define(FOO, foo).\n")?;
+        std::fs::write(&file, "-include(\"foo.hrl\").\n\n\nfoo() ->\n    ?FOO.\n")?;
+
+        let options = crate::Options::new()
+            .include_cache_dir(&cache_dir)
+            .format_cache_dir(&cache_dir);
+        options
+            .clone()
+            .format_file::<crate::items::Module, _>(&file)?;
+        std::fs::create_dir_all(cache_dir.join("v999"))?;
+        std::fs::write(cache_dir.join("v999/old"), "{}")?;
+
+        let before = stats(&cache_dir);
+        assert_eq!(before.include_entries, 2);
+        assert_eq!(before.format_entries, 1);
+
+        // Only the entry of the other version is removed.
+        let removed = prune(&cache_dir, None)?;
+        assert_eq!((removed.include_entries, removed.format_entries), (1, 0));
+
+        // The entries referring to a removed file are removed.
+        std::fs::remove_file(&header)?;
+        let removed = prune(&cache_dir, None)?;
+        assert_eq!((removed.include_entries, removed.format_entries), (1, 1));
+
+        options.format_file::<crate::items::Module, _>(&file)?;
+        assert_eq!(stats(&cache_dir).format_entries, 1);
+        assert_eq!(clear(&cache_dir)?.format_entries, 1);
+        assert_eq!(stats(&cache_dir), CacheStats::default());
+        Ok(())
+    }
+}
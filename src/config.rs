@@ -0,0 +1,598 @@
+//! Project configuration files (`.efmt.toml` or `efmt.config`).
+//!
+//! A configuration file is searched from the current directory up to the root
+//! (the language server searches from the directory of each document instead).
+//! `.efmt.toml` is written in TOML, and `efmt.config` consists of Erlang terms in the same format as
+//! the items of the `{efmt, [...]}` entry in `rebar.config`.
+//!
+//! ```toml
+//! print_width = 100
+//! include_search_dirs = ["include"]
+//! defines = ["TEST", "VSN=\"1.0\""]
+//!
+//! [[overrides]]
+//! files = ["test/**"]
+//! print_width = 140
+//! ```
+use crate::files::RebarConfigValue;
+use crate::line_ending::LineEnding;
+use std::path::{Path, PathBuf};
+
+/// The names of the configuration files (in priority order).
+pub const CONFIG_FILE_NAMES: [&str; 2] = [".efmt.toml", "efmt.config"];
+
+/// Searches for a configuration file from the current directory up to the root.
+pub fn find_config_file() -> Option<PathBuf> {
+    find_config_file_from(std::env::current_dir().ok()?)
+}
+
+/// Searches for a configuration file from the given directory up to the root.
+pub fn find_config_file_from<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    dir.as_ref().ancestors().find_map(|dir| {
+        CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Options specified in a configuration file or in the `{efmt, [...]}` entry of `rebar.config`.
+///
+/// `None` and empty fields mean that the options are not specified.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub print_width: Option<usize>,
+    pub parallel: Option<bool>,
+    pub include_search_dirs: Vec<PathBuf>,
+
+    /// Directories containing applications used to resolve `-include_lib` (e.g., `apps/` of an umbrella project).
+    pub lib_dirs: Vec<PathBuf>,
+    pub otp_root: Option<PathBuf>,
+    pub include_lib_erl_fallback: Option<bool>,
+
+    /// Macro definitions in the form of `NAME[=VALUE]` (like `-D` option).
+    pub defines: Vec<String>,
+
+    /// The OTP release that determines the features enabled by default (e.g., `maybe_expr` since OTP 27).
+    pub otp_release: Option<u32>,
+    pub enable_features: Vec<String>,
+    pub disable_features: Vec<String>,
+
+    /// Line ending of the formatted text (`lf`, `crlf` or `auto`).
+    pub newline: Option<LineEnding>,
+
+    /// Regular expressions of the files excluded from the default target files.
+    pub exclude_files: Vec<String>,
+    pub disable_include: Option<bool>,
+    pub include_cache_dir: Option<PathBuf>,
+
+    /// The maximum total size of the include cache entries in bytes.
+    pub include_cache_max_size: Option<u64>,
+    pub disable_include_cache: Option<bool>,
+    pub disable_format_cache: Option<bool>,
+    pub default_off: Option<bool>,
+    pub error_tolerant: Option<bool>,
+    pub verify_idempotency: Option<bool>,
+    pub overrides: Vec<Override>,
+}
+
+impl Config {
+    /// Loads a configuration file.
+    ///
+    /// Relative paths in the file are resolved against the directory containing the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let config = if path.extension().is_some_and(|x| x == "toml") {
+            let text = std::fs::read_to_string(path)?;
+            toml::from_str(&text)?
+        } else {
+            Self::from_terms(&crate::files::load_rebar_config(path)?)
+        };
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+        Ok(config.with_base_dir(base_dir))
+    }
+
+    /// Makes a [Config] instance from Erlang terms (e.g., the items of `{efmt, [...]}` in `rebar.config`).
+    ///
+    /// Unknown or invalid options are ignored with warnings.
+    pub fn from_terms(terms: &[RebarConfigValue]) -> Self {
+        let mut config = Self::default();
+        for term in terms {
+            match config.handle_term(term) {
+                Ok(true) => {}
+                Ok(false) => log::warn!("found an unhandled efmt option: {term}"),
+                Err(e) => log::warn!("found an invalid efmt option: {term}: {e}"),
+            }
+        }
+        config
+    }
+
+    fn handle_term(&mut self, term: &RebarConfigValue) -> anyhow::Result<bool> {
+        use RebarConfigValue::{Atom, List, Tuple};
+
+        let (key, values) = match term {
+            Atom(key) => (key.as_str(), &[][..]),
+            Tuple(items) => match items.as_slice() {
+                [Atom(key), values @ ..] => (key.as_str(), values),
+                _ => return Ok(false),
+            },
+            _ => return Ok(false),
+        };
+        if let Some(flag) = self.flag_mut(key) {
+            match values {
+                [] => *flag = Some(true),
+                [Atom(v)] if v == "true" || v == "false" => *flag = Some(v == "true"),
+                _ => return Ok(false),
+            }
+            return Ok(true);
+        }
+        match (key, values) {
+            ("print_width", [v]) if v.as_usize().is_some() => self.print_width = v.as_usize(),
+            ("I" | "include_search_dir", [v]) if v.as_str().is_some() => self
+                .include_search_dirs
+                .extend(v.as_str().map(PathBuf::from)),
+            ("lib_dir", [v]) if v.as_str().is_some() => {
+                self.lib_dirs.extend(v.as_str().map(PathBuf::from))
+            }
+            ("otp_root", [v]) if v.as_str().is_some() => {
+                self.otp_root = v.as_str().map(PathBuf::from)
+            }
+            ("include_cache_max_size", [v]) if v.as_usize().is_some() => {
+                self.include_cache_max_size = v.as_usize().map(|n| n as u64)
+            }
+            ("include_cache_dir", [v]) if v.as_str().is_some() => {
+                self.include_cache_dir = v.as_str().map(PathBuf::from)
+            }
+            ("otp_release", [v]) if v.as_usize().is_some() => {
+                self.otp_release = v.as_usize().map(|n| n as u32)
+            }
+            ("feature", [Atom(name), Atom(v)]) if v == "enable" => {
+                self.enable_features.push(name.clone())
+            }
+            ("feature", [Atom(name), Atom(v)]) if v == "disable" => {
+                self.disable_features.push(name.clone())
+            }
+            ("newline", [Atom(v)]) => self.newline = Some(v.parse()?),
+            ("define", [Atom(name)]) => self.defines.push(name.clone()),
+            ("define", [Atom(name), value]) => self.defines.push(format!("{name}={value}")),
+            ("exclude_file", [v]) if v.as_str().is_some() => {
+                self.exclude_files.extend(v.as_str().map(str::to_owned))
+            }
+            ("overrides", [List(items)]) => {
+                for item in items {
+                    match Override::from_term(item) {
+                        Ok(x) => self.overrides.push(x),
+                        Err(e) => log::warn!("found an invalid efmt override: {item}: {e}"),
+                    }
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn flag_mut(&mut self, key: &str) -> Option<&mut Option<bool>> {
+        match key {
+            "parallel" => Some(&mut self.parallel),
+            "disable_include" => Some(&mut self.disable_include),
+            "include_lib_erl_fallback" => Some(&mut self.include_lib_erl_fallback),
+            "disable_include_cache" => Some(&mut self.disable_include_cache),
+            "disable_format_cache" => Some(&mut self.disable_format_cache),
+            "default_off" => Some(&mut self.default_off),
+            "error_tolerant" => Some(&mut self.error_tolerant),
+            "verify_idempotency" => Some(&mut self.verify_idempotency),
+            _ => None,
+        }
+    }
+
+    /// Resolves the relative paths in this configuration against `base_dir`.
+    pub fn with_base_dir<P: AsRef<Path>>(mut self, base_dir: P) -> Self {
+        let base_dir = base_dir.as_ref();
+        for dir in self
+            .include_search_dirs
+            .iter_mut()
+            .chain(&mut self.lib_dirs)
+        {
+            *dir = base_dir.join(&dir);
+        }
+        if let Some(dir) = &mut self.otp_root {
+            *dir = base_dir.join(&dir);
+        }
+        if let Some(dir) = &mut self.include_cache_dir {
+            *dir = base_dir.join(&dir);
+        }
+        for x in &mut self.overrides {
+            // Canonicalized here so that `Override::matches()` doesn't need to do it for each file.
+            x.base_dir = canonicalize(base_dir.join(&x.base_dir));
+            for dir in &mut x.include_search_dirs {
+                *dir = base_dir.join(&dir);
+            }
+        }
+        self
+    }
+
+    /// Merges two configurations.
+    ///
+    /// The options in `other` take precedence over the ones in `self`.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            print_width: other.print_width.or(self.print_width),
+            parallel: other.parallel.or(self.parallel),
+            include_search_dirs: concat(other.include_search_dirs, self.include_search_dirs),
+            lib_dirs: concat(other.lib_dirs, self.lib_dirs),
+            // Later definitions win.
+            defines: concat(self.defines, other.defines),
+            otp_release: other.otp_release.or(self.otp_release),
+            enable_features: concat(self.enable_features, other.enable_features),
+            disable_features: concat(self.disable_features, other.disable_features),
+            newline: other.newline.or(self.newline),
+            exclude_files: concat(self.exclude_files, other.exclude_files),
+            disable_include: other.disable_include.or(self.disable_include),
+            otp_root: other.otp_root.or(self.otp_root),
+            include_lib_erl_fallback: other
+                .include_lib_erl_fallback
+                .or(self.include_lib_erl_fallback),
+            include_cache_dir: other.include_cache_dir.or(self.include_cache_dir),
+            include_cache_max_size: other.include_cache_max_size.or(self.include_cache_max_size),
+            disable_include_cache: other.disable_include_cache.or(self.disable_include_cache),
+            disable_format_cache: other.disable_format_cache.or(self.disable_format_cache),
+            default_off: other.default_off.or(self.default_off),
+            error_tolerant: other.error_tolerant.or(self.error_tolerant),
+            verify_idempotency: other.verify_idempotency.or(self.verify_idempotency),
+            // Later overrides win.
+            overrides: concat(self.overrides, other.overrides),
+        }
+    }
+
+    /// Returns the overrides applicable to `file` (in ascending order of precedence).
+    pub fn overrides_for<'a>(&'a self, file: &Path) -> impl 'a + Iterator<Item = &'a Override> {
+        let file = canonicalize(file.to_path_buf());
+        self.overrides
+            .iter()
+            .filter(move |x| x.matches_canonicalized(&file))
+    }
+
+    /// Returns the configuration for `file` where the matching overrides are applied.
+    ///
+    /// The `overrides` field of the returned configuration is empty.
+    pub fn resolve_for(&self, file: &Path) -> Self {
+        let mut config = Self {
+            overrides: Vec::new(),
+            ..self.clone()
+        };
+        for x in self.overrides_for(file) {
+            config = config.merge(Self {
+                print_width: x.print_width,
+                include_search_dirs: x.include_search_dirs.clone(),
+                defines: x.defines.clone(),
+                disable_include: x.disable_include,
+                default_off: x.default_off,
+                error_tolerant: x.error_tolerant,
+                verify_idempotency: x.verify_idempotency,
+                ..Default::default()
+            });
+        }
+        config
+    }
+}
+
+fn canonicalize(path: PathBuf) -> PathBuf {
+    // An empty path (e.g., the parent of `efmt.config`) means the current directory.
+    let target = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &path
+    };
+    std::fs::canonicalize(target).unwrap_or(path)
+}
+
+fn concat<T>(mut xs: Vec<T>, ys: Vec<T>) -> Vec<T> {
+    xs.extend(ys);
+    xs
+}
+
+/// Options applied only to the files matching the glob patterns.
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Override {
+    /// Glob patterns relative to the directory containing the configuration file (e.g., `"test/**"`).
+    pub files: Vec<Glob>,
+    pub print_width: Option<usize>,
+    pub include_search_dirs: Vec<PathBuf>,
+    pub defines: Vec<String>,
+    pub disable_include: Option<bool>,
+    pub default_off: Option<bool>,
+    pub error_tolerant: Option<bool>,
+    pub verify_idempotency: Option<bool>,
+
+    #[serde(skip)]
+    base_dir: PathBuf,
+}
+
+impl Override {
+    /// Makes an [Override] instance from an Erlang term such as `{"test/**", [{print_width, 140}]}`.
+    ///
+    /// The first element of the tuple can also be a list of glob patterns.
+    pub fn from_term(term: &RebarConfigValue) -> anyhow::Result<Self> {
+        use RebarConfigValue::{List, Tuple};
+
+        let Tuple(items) = term else {
+            anyhow::bail!("expected `{{Glob | [Glob], [Option]}}` but got {term}");
+        };
+        let [patterns, List(options)] = items.as_slice() else {
+            anyhow::bail!("expected `{{Glob | [Glob], [Option]}}` but got {term}");
+        };
+
+        let mut x = Self::default();
+        let patterns = match patterns {
+            List(patterns) => patterns.as_slice(),
+            _ => std::slice::from_ref(patterns),
+        };
+        for pattern in patterns {
+            let Some(pattern) = pattern.as_str() else {
+                anyhow::bail!("expected a glob pattern string but got {pattern}");
+            };
+            x.files.push(pattern.parse()?);
+        }
+        let Config {
+            print_width,
+            parallel: None,
+            include_search_dirs,
+            lib_dirs,
+            otp_root: None,
+            include_lib_erl_fallback: None,
+            defines,
+            otp_release: None,
+            enable_features,
+            disable_features,
+            newline: None,
+            exclude_files,
+            disable_include,
+            include_cache_dir: None,
+            include_cache_max_size: None,
+            disable_include_cache: None,
+            disable_format_cache: None,
+            default_off,
+            error_tolerant,
+            verify_idempotency,
+            overrides,
+        } = Config::from_terms(options)
+        else {
+            anyhow::bail!("found options that cannot be overridden per file: {term}");
+        };
+        anyhow::ensure!(
+            lib_dirs.is_empty()
+                && enable_features.is_empty()
+                && disable_features.is_empty()
+                && exclude_files.is_empty()
+                && overrides.is_empty(),
+            "found options that cannot be overridden per file: {term}"
+        );
+        x.print_width = print_width;
+        x.include_search_dirs = include_search_dirs;
+        x.defines = defines;
+        x.disable_include = disable_include;
+        x.default_off = default_off;
+        x.error_tolerant = error_tolerant;
+        x.verify_idempotency = verify_idempotency;
+        Ok(x)
+    }
+
+    /// Returns `true` if `file` matches any of the glob patterns.
+    pub fn matches(&self, file: &Path) -> bool {
+        self.matches_canonicalized(&canonicalize(file.to_path_buf()))
+    }
+
+    fn matches_canonicalized(&self, file: &Path) -> bool {
+        let Some(relative) = file
+            .strip_prefix(&self.base_dir)
+            .ok()
+            .and_then(|p| p.to_str())
+        else {
+            return false;
+        };
+        let relative = relative.replace('\\', "/");
+        self.files.iter().any(|glob| glob.is_match(&relative))
+    }
+}
+
+/// A glob pattern.
+///
+/// `*` matches any sequence of characters except `/`, `**` matches any sequence of path components,
+/// and `?` matches any single character except `/`.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(try_from = "String")]
+pub struct Glob {
+    pattern: String,
+    regex: regex::Regex,
+}
+
+impl Glob {
+    /// Returns `true` if `path` (separated by `/`) matches this pattern.
+    pub fn is_match(&self, path: &str) -> bool {
+        self.regex.is_match(path)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+}
+
+impl std::str::FromStr for Glob {
+    type Err = regex::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        regex.push_str("(?:.*/)?");
+                    } else {
+                        regex.push_str(".*");
+                    }
+                }
+                '*' => regex.push_str("[^/]*"),
+                '?' => regex.push_str("[^/]"),
+                _ => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            regex: regex::Regex::new(&regex)?,
+        })
+    }
+}
+
+impl TryFrom<String> for Glob {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        pattern.parse()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_works() {
+        let glob: Glob = "test/**".parse().unwrap();
+        assert!(glob.is_match("test/foo_SUITE.erl"));
+        assert!(glob.is_match("test/data/foo.erl"));
+        assert!(!glob.is_match("src/test/foo.erl"));
+
+        let glob: Glob = "**/*_SUITE.erl".parse().unwrap();
+        assert!(glob.is_match("foo_SUITE.erl"));
+        assert!(glob.is_match("apps/foo/test/foo_SUITE.erl"));
+        assert!(!glob.is_match("apps/foo/test/foo_SUITE.erl.bak"));
+    }
+
+    #[test]
+    fn load_config_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let toml_path = dir.path().join(".efmt.toml");
+        std::fs::write(
+            &toml_path,
+            indoc::indoc! {r#"
+                print_width = 100
+                include_search_dirs = ["include"]
+                defines = ["TEST"]
+                disable_features = ["maybe_expr"]
+                newline = "crlf"
+
+                [[overrides]]
+                files = ["test/**"]
+                print_width = 140
+            "#},
+        )?;
+        let terms_path = dir.path().join("efmt.config");
+        std::fs::write(
+            &terms_path,
+            indoc::indoc! {r#"
+                {print_width, 100}.
+                {include_search_dir, "include"}.
+                {define, 'TEST'}.
+                {feature, maybe_expr, disable}.
+                {newline, crlf}.
+                {overrides, [{"test/**", [{print_width, 140}]}]}.
+            "#},
+        )?;
+        assert_eq!(find_config_file_from(dir.path()), Some(toml_path.clone()));
+
+        for path in [toml_path, terms_path] {
+            let config = Config::load(&path)?;
+            assert_eq!(config.print_width, Some(100));
+            assert_eq!(config.include_search_dirs, [dir.path().join("include")]);
+            assert_eq!(config.defines, ["TEST"]);
+            assert_eq!(config.disable_features, ["maybe_expr"]);
+            assert_eq!(config.newline, Some(LineEnding::Crlf));
+
+            let test_file = dir.path().join("test/foo_SUITE.erl");
+            let overrides = config.overrides_for(&test_file).collect::<Vec<_>>();
+            assert_eq!(overrides.len(), 1);
+            assert_eq!(overrides[0].print_width, Some(140));
+            assert_eq!(
+                config
+                    .overrides_for(&dir.path().join("src/foo.erl"))
+                    .count(),
+                0
+            );
+        }
+
+        let merged = Config {
+            print_width: Some(80),
+            defines: vec!["A".to_owned()],
+            ..Default::default()
+        }
+        .merge(Config {
+            print_width: Some(100),
+            defines: vec!["B".to_owned()],
+            ..Default::default()
+        });
+        assert_eq!(merged.print_width, Some(100));
+        assert_eq!(merged.defines, ["A", "B"]);
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_for_works() -> anyhow::Result<()> {
+        let config: Config = toml::from_str(indoc::indoc! {r#"
+            print_width = 100
+            defines = ["A"]
+
+            [[overrides]]
+            files = ["test/**", "*.hrl"]
+            print_width = 140
+            default_off = true
+            defines = ["B"]
+
+            [[overrides]]
+            files = ["test/data/**"]
+            print_width = 80
+        "#})?;
+        let config = config.with_base_dir("/project");
+
+        let resolved = config.resolve_for(Path::new("/project/src/foo.erl"));
+        assert_eq!(resolved.print_width, Some(100));
+        assert_eq!(resolved.default_off, None);
+
+        let resolved = config.resolve_for(Path::new("/project/foo.hrl"));
+        assert_eq!(resolved.print_width, Some(140));
+        assert_eq!(resolved.default_off, Some(true));
+        assert_eq!(resolved.defines, ["A", "B"]);
+        assert!(resolved.overrides.is_empty());
+
+        let resolved = config.resolve_for(Path::new("/project/test/data/foo.erl"));
+        assert_eq!(resolved.print_width, Some(80));
+        assert_eq!(resolved.default_off, Some(true));
+
+        let term = RebarConfigValue::Tuple(vec![
+            RebarConfigValue::String("test/**".to_owned()),
+            RebarConfigValue::List(vec![RebarConfigValue::Atom("parallel".to_owned())]),
+        ]);
+        assert!(Override::from_term(&term).is_err());
+
+        // Invalid overrides are ignored instead of failing the whole configuration.
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("efmt.config");
+        std::fs::write(
+            &path,
+            r#"{print_width, 100}. {overrides, [{"test/**", [parallel]}, {"*.hrl", [{print_width, 140}]}]}."#,
+        )?;
+        let config = Config::load(&path)?;
+        assert_eq!(config.print_width, Some(100));
+        assert_eq!(config.overrides.len(), 1);
+        assert_eq!(
+            config.resolve_for(&dir.path().join("foo.hrl")).print_width,
+            Some(140)
+        );
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use similar::TextDiff;
+use similar::{DiffTag, TextDiff};
 use std::path::Path;
 
 pub fn text_diff<P: AsRef<Path>>(original: &str, formatted: &str, file: P) -> String {
@@ -11,3 +11,51 @@ pub fn text_diff<P: AsRef<Path>>(original: &str, formatted: &str, file: P) -> St
         diff.unified_diff().to_string()
     }
 }
+
+/// A changed region between an original text and the formatted one.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Hunk {
+    /// The first changed line of the original text (1-origin).
+    ///
+    /// If no lines are removed, this is the line before which new lines are inserted.
+    pub original_start_line: usize,
+
+    /// The number of the changed lines of the original text.
+    pub original_line_count: usize,
+
+    /// The first changed line of the formatted text (1-origin).
+    pub formatted_start_line: usize,
+
+    /// The number of the changed lines of the formatted text.
+    pub formatted_line_count: usize,
+
+    /// The unified diff of this hunk (including some context lines).
+    pub diff: String,
+}
+
+/// Returns the changed regions between `original` and `formatted`.
+pub fn text_hunks(original: &str, formatted: &str) -> Vec<Hunk> {
+    let diff = TextDiff::from_lines(original, formatted);
+    let unified_diff = diff.unified_diff();
+    unified_diff
+        .iter_hunks()
+        .filter_map(|hunk| {
+            let mut changes = hunk
+                .ops()
+                .iter()
+                .filter(|op| op.tag() != DiffTag::Equal)
+                .map(|op| (op.old_range(), op.new_range()));
+            let (old, new) = changes.next()?;
+            let (old, new) = changes.fold((old, new), |(old, new), (o, n)| {
+                (old.start..o.end, new.start..n.end)
+            });
+            Some(Hunk {
+                original_start_line: old.start + 1,
+                original_line_count: old.len(),
+                formatted_start_line: new.start + 1,
+                formatted_line_count: new.len(),
+                diff: hunk.to_string(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,114 @@
+//! Source file encodings.
+//!
+//! As with the Erlang compiler, a file is regarded as UTF-8 unless its first or second line contains
+//! an encoding comment such as `%% -*- coding: latin-1 -*-`.
+use std::path::Path;
+
+/// The encoding of an Erlang source file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Latin1,
+}
+
+impl Encoding {
+    /// Detects the encoding from the `coding:` comment in the first two lines of `bytes`.
+    ///
+    /// Returns `None` if there is no such comment or the encoding name is unknown.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        bytes.split(|b| *b == b'\n').take(2).find_map(|line| {
+            let comment = &line[line.iter().position(|b| *b == b'%')?..];
+            let line = String::from_utf8_lossy(comment).to_ascii_lowercase();
+            let (_, rest) = line.split_once("coding")?;
+            let rest = rest.trim_start().strip_prefix([':', '='])?.trim_start();
+            let name = rest
+                .split(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+                .next()?;
+            match name {
+                "latin-1" | "latin1" | "iso-8859-1" => Some(Self::Latin1),
+                "utf-8" | "utf8" => Some(Self::Utf8),
+                _ => None,
+            }
+        })
+    }
+
+    /// Decodes `bytes` into a string.
+    pub fn decode(self, bytes: Vec<u8>) -> std::io::Result<String> {
+        match self {
+            Self::Utf8 => String::from_utf8(bytes).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{e} (add a `%% coding: latin-1` comment if the file is encoded in latin-1)"),
+                )
+            }),
+            Self::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+        }
+    }
+
+    /// Encodes `text` into bytes.
+    pub fn encode(self, text: &str) -> std::io::Result<Vec<u8>> {
+        match self {
+            Self::Utf8 => Ok(text.as_bytes().to_vec()),
+            Self::Latin1 => text
+                .chars()
+                .map(|c| {
+                    u8::try_from(c).map_err(|_| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("{c:?} cannot be encoded in latin-1"),
+                        )
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Decodes the content of an Erlang source file according to its encoding comment.
+pub fn decode(bytes: Vec<u8>) -> std::io::Result<(String, Encoding)> {
+    let encoding = Encoding::detect(&bytes).unwrap_or_default();
+    Ok((encoding.decode(bytes)?, encoding))
+}
+
+/// Reads an Erlang source file and decodes it according to its encoding comment.
+pub fn read_file<P: AsRef<Path>>(path: P) -> std::io::Result<(String, Encoding)> {
+    decode(std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_works() {
+        assert_eq!(Encoding::detect(b"-module(foo)."), None);
+        assert_eq!(
+            Encoding::detect(b"%% -*- coding: latin-1 -*-\n-module(foo)."),
+            Some(Encoding::Latin1)
+        );
+        assert_eq!(
+            Encoding::detect(b"#!/usr/bin/env escript\n%% coding=UTF-8\n"),
+            Some(Encoding::Utf8)
+        );
+        assert_eq!(
+            Encoding::detect(b"-module(foo).\n\n%% coding: latin-1\n"),
+            None
+        );
+        assert_eq!(Encoding::detect(b"coding: latin-1\n"), None);
+    }
+
+    #[test]
+    fn decode_and_encode_work() -> std::io::Result<()> {
+        let bytes = b"%% coding: latin-1\nfoo() -> \"\xe5\xe4\xf6\".\n".to_vec();
+        let (text, encoding) = decode(bytes.clone())?;
+        assert_eq!(encoding, Encoding::Latin1);
+        assert_eq!(text, "%% coding: latin-1\nfoo() -> \"åäö\".\n");
+        assert_eq!(encoding.encode(&text)?, bytes);
+        assert!(encoding.encode("\"€\"").is_err());
+
+        let bytes = b"foo() -> \"\xe5\".\n".to_vec();
+        assert!(decode(bytes).is_err());
+        Ok(())
+    }
+}
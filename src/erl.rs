@@ -1,11 +1,14 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::SystemTime;
 
 pub fn erl_eval(expr: &str) -> anyhow::Result<String> {
     let args = [
         "-noshell".to_owned(),
         "-eval".to_owned(),
-        format!("io:format(\"~s\", [{}]), halt().", expr),
+        format!("io:format(\"~ts\", [{}]), halt().", expr),
     ];
     let output = Command::new("erl")
         .args(args)
@@ -22,3 +25,141 @@ pub fn erl_eval(expr: &str) -> anyhow::Result<String> {
 pub fn code_lib_dir(app_name: &str) -> anyhow::Result<PathBuf> {
     erl_eval(&format!("code:lib_dir({})", app_name)).map(PathBuf::from)
 }
+
+/// Finds the directory of an application in `lib_dirs` without running `erl` command (like `code:lib_dir/1`).
+///
+/// Both `DIR/APP` and `DIR/APP-VSN` are searched, and the highest version is chosen if a directory contains multiple ones.
+/// The entries of each directory are cached and read again only when the modification time of the directory changes
+/// (e.g., an application is added by `rebar3 compile` while a language server is running).
+pub fn find_lib_dir(app_name: &str, lib_dirs: &[PathBuf]) -> Option<PathBuf> {
+    type Entries = (Option<SystemTime>, Arc<Vec<PathBuf>>);
+    static ENTRIES: OnceLock<Mutex<HashMap<PathBuf, Entries>>> = OnceLock::new();
+
+    for lib_dir in lib_dirs {
+        let mtime = std::fs::metadata(lib_dir).and_then(|m| m.modified()).ok();
+        let entries = {
+            let mut cache = ENTRIES
+                .get_or_init(Default::default)
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            match cache.get(lib_dir) {
+                Some((cached_mtime, entries)) if *cached_mtime == mtime => entries.clone(),
+                _ => {
+                    let entries = Arc::new(read_subdirs(lib_dir));
+                    cache.insert(lib_dir.clone(), (mtime, entries.clone()));
+                    entries
+                }
+            }
+        };
+        let found = entries
+            .iter()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                let vsn = name.strip_prefix(app_name)?;
+                if vsn.is_empty() {
+                    return Some((Vec::new(), path));
+                }
+                let vsn = vsn.strip_prefix('-')?;
+                vsn.starts_with(|c: char| c.is_ascii_digit())
+                    .then(|| (version_key(vsn), path))
+            })
+            .max();
+        if let Some((_, path)) = found {
+            return Some(path.clone());
+        }
+    }
+    None
+}
+
+fn read_subdirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+fn version_key(vsn: &str) -> Vec<(u64, String)> {
+    vsn.split(['.', '-'])
+        .map(|part| match part.parse() {
+            Ok(n) => (n, String::new()),
+            Err(_) => (0, part.to_owned()),
+        })
+        .collect()
+}
+
+/// Returns the directories searched for applications by default:
+/// the ones in `ERL_LIBS` envvar and `OTP_ROOT/lib`.
+///
+/// If `otp_root` is `None`, it is detected from the location of `erl` command (see [find_otp_root()]).
+pub fn default_lib_dirs(otp_root: Option<&Path>) -> Vec<PathBuf> {
+    let mut dirs = std::env::var_os("ERL_LIBS")
+        .map(|paths| std::env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if let Some(root) = otp_root.map(Path::to_path_buf).or_else(find_otp_root) {
+        dirs.push(root.join("lib"));
+    }
+    dirs
+}
+
+/// Detects the root directory of Erlang/OTP (like `code:root_dir/0`) from the location of `erl` command in `PATH`.
+pub fn find_otp_root() -> Option<PathBuf> {
+    static OTP_ROOT: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+    OTP_ROOT
+        .get_or_init(|| {
+            let erl = if cfg!(windows) { "erl.exe" } else { "erl" };
+            let path = std::env::split_paths(&std::env::var_os("PATH")?)
+                .map(|dir| dir.join(erl))
+                .find(|path| path.is_file())?;
+            let root = std::fs::canonicalize(path)
+                .ok()?
+                .parent()?
+                .parent()?
+                .to_path_buf();
+
+            // In case that `erl` is not a symlink to `OTP_ROOT/bin/erl` (e.g., `$PREFIX/bin/erl` for `$PREFIX/lib/erlang/`).
+            let root = if root.join("lib/erlang/lib").is_dir() {
+                root.join("lib/erlang")
+            } else {
+                root
+            };
+            log::debug!("detected the OTP root directory: {root:?}");
+            Some(root)
+        })
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_lib_dir_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let lib_dir = dir.path().to_path_buf();
+        for name in ["foo-1.9.0", "foo-1.10.0", "foo_bar-2.0", "baz", "qux-rc"] {
+            std::fs::create_dir(lib_dir.join(name))?;
+        }
+        let lib_dirs = [PathBuf::from("/no/such/dir"), lib_dir.clone()];
+
+        assert_eq!(
+            find_lib_dir("foo", &lib_dirs),
+            Some(lib_dir.join("foo-1.10.0"))
+        );
+        assert_eq!(find_lib_dir("baz", &lib_dirs), Some(lib_dir.join("baz")));
+        assert_eq!(find_lib_dir("qux", &lib_dirs), None);
+        assert_eq!(find_lib_dir("bar", &lib_dirs), None);
+
+        // The listing is refreshed when the directory changes.
+        let build_dir = lib_dir.join("_build/default/lib");
+        let lib_dirs = [build_dir.clone()];
+        assert_eq!(find_lib_dir("bar", &lib_dirs), None);
+        std::fs::create_dir_all(build_dir.join("bar"))?;
+        assert_eq!(find_lib_dir("bar", &lib_dirs), Some(build_dir.join("bar")));
+        Ok(())
+    }
+}
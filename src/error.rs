@@ -1,6 +1,122 @@
 use crate::span::Position;
 use std::fmt::Write as _;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// Possible errors returned by [Options][crate::Options]'s formatting methods.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Failed to read a target file.
+    #[error("Failed to read {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    /// Failed to tokenize a target text.
+    #[error(transparent)]
+    Tokenize(crate::parse::Error),
+
+    /// Failed to parse a target text.
+    #[error(transparent)]
+    Parse(crate::parse::Error),
+
+    /// Failed to parse a macro definition given via [Options::define_macro()][crate::Options::define_macro].
+    #[error("Invalid macro definition `{name}={value}`: {}", .source.reason())]
+    InvalidMacroDefinition {
+        name: String,
+        value: String,
+        source: crate::parse::Error,
+    },
+
+    /// The formatted text has different tokens from the original one (maybe an efmt bug).
+    #[error("Found a token mismatch between the original text and the formatted one (maybe efmt bug):{message}")]
+    TokenMismatch {
+        path: Option<PathBuf>,
+
+        /// The position of the mismatched token in the original text (`None` if the formatted text has an extra token).
+        original_position: Option<Position>,
+
+        /// The position of the mismatched token in the formatted text (`None` if the original text has an extra token).
+        formatted_position: Option<Position>,
+
+        message: String,
+    },
+
+    /// Formatting the formatted text again yields a different text (maybe an efmt bug).
+    #[error(
+        "Formatting the formatted text again yields a different text (maybe efmt bug):\n{diff}"
+    )]
+    NotIdempotent {
+        path: Option<PathBuf>,
+
+        /// The first position in the formatted text where the reformatted text differs.
+        position: Position,
+
+        diff: String,
+    },
+}
+
+impl Error {
+    /// Returns the path of the file where the error occurred.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Io { path, .. } => Some(path),
+            Self::Tokenize(e) | Self::Parse(e) => e.path(),
+            Self::InvalidMacroDefinition { .. } => None,
+            Self::TokenMismatch { path, .. } | Self::NotIdempotent { path, .. } => path.as_deref(),
+        }
+    }
+
+    /// Returns the position where the error occurred.
+    pub fn position(&self) -> Option<Position> {
+        match self {
+            Self::Io { .. } | Self::InvalidMacroDefinition { .. } => None,
+            Self::Tokenize(e) | Self::Parse(e) => Some(e.position()),
+            Self::TokenMismatch {
+                original_position,
+                formatted_position,
+                ..
+            } => original_position.or(*formatted_position),
+            Self::NotIdempotent { position, .. } => Some(*position),
+        }
+    }
+
+    /// Returns the line number (1-origin) where the error occurred.
+    pub fn line(&self) -> Option<usize> {
+        self.position().map(|p| p.line())
+    }
+
+    /// Returns the column number (1-origin) where the error occurred.
+    pub fn column(&self) -> Option<usize> {
+        self.position().map(|p| p.column())
+    }
+
+    /// Returns a short description of the error (without the position information).
+    pub fn reason(&self) -> String {
+        match self {
+            Self::Io { source, .. } => source.to_string(),
+            Self::Tokenize(e) | Self::Parse(e) => e.reason(),
+            Self::InvalidMacroDefinition { .. } => self.to_string(),
+            Self::TokenMismatch { .. } => "token mismatch".to_owned(),
+            Self::NotIdempotent { .. } => "not idempotent".to_owned(),
+        }
+    }
+
+    /// Returns the rendered error message.
+    pub fn message(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl From<crate::parse::Error> for Error {
+    fn from(e: crate::parse::Error) -> Self {
+        if matches!(e, crate::parse::Error::TokenizeError { .. }) {
+            Self::Tokenize(e)
+        } else {
+            Self::Parse(e)
+        }
+    }
+}
 
 pub fn generate_error_message<P: AsRef<Path>>(
     text: &str,
@@ -40,3 +156,33 @@ fn get_line_string(text: &str, position: Position) -> &str {
         .unwrap_or_else(|| text.len());
     text[line_start..line_end].trim_matches(char::is_control)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::Module;
+
+    #[test]
+    fn error_accessors_work() {
+        let e = crate::Options::new()
+            .format_file::<Module, _>("not_exist.erl")
+            .unwrap_err();
+        assert!(matches!(e, Error::Io { .. }));
+        assert_eq!(e.path(), Some(Path::new("not_exist.erl")));
+        assert_eq!(e.position(), None);
+
+        let e = crate::Options::new()
+            .format_text::<Module>("foo() ->\n    bar(.")
+            .unwrap_err();
+        assert!(matches!(e, Error::Parse(_)));
+        assert_eq!(e.path(), None);
+        assert_eq!((e.line(), e.column()), (Some(2), Some(9)));
+        assert_eq!(e.reason(), "unexpected token");
+
+        let e = crate::Options::new()
+            .format_text::<Module>("foo() -> \"bar.")
+            .unwrap_err();
+        assert!(matches!(e, Error::Tokenize(_)));
+        assert_eq!((e.line(), e.column()), (Some(1), Some(10)));
+    }
+}
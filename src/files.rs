@@ -1,39 +1,244 @@
-use crate::items::{Config, Expr};
-use crate::parse::{IncludeOptions, TokenStream};
+use crate::range::FormatRange;
+use erl_tokenize::values::Symbol;
+use erl_tokenize::{LexicalToken, PositionRange as _};
+use num::{BigInt, ToPrimitive as _};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 pub fn find_rebar_config_dir() -> Option<PathBuf> {
-    let mut dir = std::env::current_dir().ok()?;
-    while !dir.join("rebar.config").exists() {
-        dir = dir.parent()?.to_path_buf();
+    find_rebar_config_dir_from(std::env::current_dir().ok()?)
+}
+
+/// Searches for the directory containing `rebar.config` from the given directory up to the root.
+///
+/// If the found directory is an application of an umbrella project (see [find_project_apps()]),
+/// the root directory of the umbrella project is returned instead.
+pub fn find_rebar_config_dir_from<P: AsRef<Path>>(dir: P) -> Option<PathBuf> {
+    let find = |dir: &Path| {
+        dir.ancestors()
+            .find(|dir| dir.join("rebar.config").exists())
+            .map(Path::to_path_buf)
+    };
+
+    let mut dir = find(dir.as_ref())?;
+    while let Some(parent) = dir.parent().and_then(find) {
+        let rebar_config = load_rebar_config(parent.join("rebar.config")).unwrap_or_default();
+        let is_app = find_project_apps(&parent, &rebar_config)
+            .iter()
+            .any(|app| parent.join(app) == dir);
+        if !is_app {
+            break;
+        }
+        dir = parent;
     }
     Some(dir)
 }
 
+/// Returns the application directories (relative to `rebar_config_dir`) of an umbrella project.
+///
+/// The directories are searched according to the `project_app_dirs` entry in `rebar.config`
+/// (`["apps/*", "lib/*", "."]` by default), and the ones containing `src/*.app.src` are regarded as applications.
+/// Note that the top-level directory itself is excluded from the result.
+pub fn find_project_apps<P: AsRef<Path>>(
+    rebar_config_dir: P,
+    rebar_config: &[RebarConfigValue],
+) -> Vec<PathBuf> {
+    let patterns = rebar_config
+        .iter()
+        .find_map(|x| match x.as_kv_tuple() {
+            Some(("project_app_dirs", RebarConfigValue::List(xs))) => {
+                Some(xs.iter().filter_map(|x| x.as_str()).collect::<Vec<_>>())
+            }
+            _ => None,
+        })
+        .unwrap_or_else(|| vec!["apps/*", "lib/*", "."]);
+
+    let root = rebar_config_dir.as_ref();
+    let mut apps = Vec::new();
+    for pattern in patterns {
+        for dir in expand_dir_pattern(root, pattern) {
+            let Ok(app) = dir.strip_prefix(root).map(Path::to_path_buf) else {
+                continue;
+            };
+            if app.as_os_str().is_empty() || apps.contains(&app) || !is_app_dir(&dir) {
+                continue;
+            }
+            apps.push(app);
+        }
+    }
+    apps
+}
+
+/// Returns the `_build/PROFILE/lib/` directories of a rebar3 project in the order of precedence.
+///
+/// The directory of the given profiles (combined like `_build/test+prod/`) comes first,
+/// then `_build/default/lib/`, and the other existing profile directories are used as a last resort.
+pub fn find_build_lib_dirs<P: AsRef<Path>>(
+    rebar_config_dir: P,
+    profiles: &[String],
+) -> Vec<PathBuf> {
+    let root = rebar_config_dir.as_ref();
+    let mut dirs = Vec::new();
+    if !profiles.is_empty() {
+        dirs.push(root.join(format!("_build/{}/lib/", profiles.join("+"))));
+    }
+    dirs.push(root.join("_build/default/lib/"));
+    if let Ok(entries) = std::fs::read_dir(root.join("_build/")) {
+        let mut others = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path().join("lib/"))
+            .filter(|dir| dir.is_dir() && !dirs.contains(dir))
+            .collect::<Vec<_>>();
+        others.sort();
+        dirs.extend(others);
+    }
+    dirs
+}
+
+/// Detects the OTP release (e.g., `27`) from the `releases/` directory of the Erlang/OTP root.
+///
+/// If `otp_root` is `None`, the root directory is detected from the location of `erl` command.
+pub fn find_otp_release(otp_root: Option<&Path>) -> Option<u32> {
+    let root = otp_root
+        .map(Path::to_path_buf)
+        .or_else(crate::erl::find_otp_root)?;
+    std::fs::read_dir(root.join("releases"))
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .max()
+}
+
+fn expand_dir_pattern(root: &Path, pattern: &str) -> Vec<PathBuf> {
+    let subdirs = |dir: &Path| -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut dirs = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| !name.starts_with('.') && name != "_build")
+            })
+            .collect::<Vec<_>>();
+        dirs.sort();
+        dirs
+    };
+
+    let mut dirs = vec![root.to_path_buf()];
+    for component in pattern.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        let mut next = Vec::new();
+        for dir in dirs {
+            if component == "**" {
+                let mut stack = vec![dir];
+                while let Some(dir) = stack.pop() {
+                    stack.extend(subdirs(&dir));
+                    next.push(dir);
+                }
+            } else if component.contains(['*', '?']) {
+                let Ok(glob) = component.parse::<crate::config::Glob>() else {
+                    continue;
+                };
+                next.extend(subdirs(&dir).into_iter().filter(|path| {
+                    path.file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| glob.is_match(name))
+                }));
+            } else if dir.join(component).is_dir() {
+                next.push(dir.join(component));
+            }
+        }
+        dirs = next;
+    }
+    dirs
+}
+
+fn is_app_dir(dir: &Path) -> bool {
+    let Ok(entries) = std::fs::read_dir(dir.join("src")) else {
+        return false;
+    };
+    entries.filter_map(|entry| entry.ok()).any(|entry| {
+        entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.ends_with(".app.src") || name.ends_with(".app.src.script"))
+    })
+}
+
+/// Loads the Erlang terms in a `rebar.config` (or a file in the same format).
+///
+/// Top-level terms that contain unsupported constructs (e.g., function calls, variables,
+/// improper lists or binaries with size specifications) are skipped with warnings.
 pub fn load_rebar_config<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<RebarConfigValue>> {
-    let text = std::fs::read_to_string(&path)?;
-    let mut tokenizer = erl_tokenize::Tokenizer::new(text);
-    tokenizer.set_filepath(path);
-    let mut ts = TokenStream::new(tokenizer, IncludeOptions::new().disable_include());
-    let config: Config = ts.parse()?;
+    let (text, _) = crate::encoding::read_file(&path)?;
+    parse_rebar_config(text, path.as_ref())
+}
 
+/// Evaluates the `rebar.config.script` file in `dir` by using `erl` command and returns the resulting terms.
+///
+/// As with rebar3, the terms in `rebar.config` (if exists) are bound to `CONFIG` variable
+/// and the path of the script is bound to `SCRIPT` variable.
+pub fn eval_rebar_config_script<P: AsRef<Path>>(dir: P) -> anyhow::Result<Vec<RebarConfigValue>> {
+    let path_term = |path: PathBuf| -> anyhow::Result<RebarConfigValue> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("non UTF-8 path: {path:?}"))?;
+        Ok(RebarConfigValue::String(path.to_owned()))
+    };
+    let script_path = dir.as_ref().join("rebar.config.script");
+    let script = path_term(script_path.clone())?;
+    let config = path_term(dir.as_ref().join("rebar.config"))?;
+    let expr = format!(
+        "begin \
+           Config = case file:consult({config}) of {{ok, C}} -> C; {{error, enoent}} -> [] end, \
+           {{ok, Result}} = file:script({script}, [{{'CONFIG', Config}}, {{'SCRIPT', {script}}}]), \
+           [io_lib:format(\"~tp.~n\", [T]) || T <- Result] \
+         end"
+    );
+    let text = crate::erl::erl_eval(&expr)?;
+    parse_rebar_config(text, &script_path)
+}
+
+fn parse_rebar_config(text: String, path: &Path) -> anyhow::Result<Vec<RebarConfigValue>> {
+    let mut parser = TermParser::new(&text, path)?;
     let mut values = Vec::new();
-    for expr in config.exprs() {
-        if let Some(x) = RebarConfigValue::from_expr(expr, &ts.text()) {
-            values.push(x);
+    while !parser.is_eof() {
+        let start = parser.index;
+        let result = parser.parse_term().and_then(|value| {
+            parser.expect_symbol(Symbol::Dot)?;
+            Ok(value)
+        });
+        match result {
+            Ok(value) => values.push(value),
+            Err(e) => {
+                log::warn!("Skipped an unsupported term in {path:?}.\n{e}");
+                parser.skip_term(start);
+            }
         }
     }
     Ok(values)
 }
 
-#[derive(Debug, Clone)]
+/// An Erlang term.
+#[derive(Debug, Clone, PartialEq)]
 pub enum RebarConfigValue {
     Atom(String),
+
+    /// A string (i.e., a list of characters written as `"..."`).
+    ///
+    /// Adjacent string literals are concatenated.
     String(String),
-    Integer(u32),
+    Char(char),
+    Integer(BigInt),
+    Float(f64),
+
+    /// A binary (e.g., `<<"foo">>` or `<<1, 2, 3>>`).
+    Binary(Vec<u8>),
     List(Vec<Self>),
     Tuple(Vec<Self>),
+    Map(Vec<(Self, Self)>),
 }
 
 impl RebarConfigValue {
@@ -47,33 +252,305 @@ impl RebarConfigValue {
         }
         None
     }
-}
 
-impl RebarConfigValue {
-    fn from_expr(expr: &Expr, text: &str) -> Option<Self> {
-        if let Some(x) = expr.as_atom() {
-            Some(Self::Atom(x.to_owned()))
-        } else if let Some(x) = expr.as_string() {
-            Some(Self::String(x.to_owned()))
-        } else if let Some(x) = expr.as_u32(text) {
-            Some(Self::Integer(x))
-        } else if let Some(xs) = expr.as_list() {
-            Some(Self::List(
-                xs.iter().filter_map(|x| Self::from_expr(x, text)).collect(),
-            ))
-        } else if let Some((tag, xs)) = expr.as_tuple() {
-            Some(Self::Tuple(
-                tag.iter()
-                    .map(|tag| Self::Atom(tag.value().to_owned()))
-                    .chain(xs.iter().filter_map(|x| Self::from_expr(x, text)))
-                    .collect(),
-            ))
+    /// Returns the value as a string if this is a string or a binary (in UTF-8).
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(x) => Some(x),
+            Self::Binary(x) => std::str::from_utf8(x).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a `usize` if this is a non-negative integer within the range.
+    pub fn as_usize(&self) -> Option<usize> {
+        if let Self::Integer(x) = self {
+            x.to_usize()
         } else {
             None
         }
     }
 }
 
+impl std::fmt::Display for RebarConfigValue {
+    /// Formats the value as an Erlang term.
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn write_items<T: std::fmt::Display>(
+            f: &mut std::fmt::Formatter,
+            items: impl Iterator<Item = T>,
+            open: &str,
+            close: &str,
+        ) -> std::fmt::Result {
+            write!(f, "{open}")?;
+            for (i, item) in items.enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{item}")?;
+            }
+            write!(f, "{close}")
+        }
+
+        fn escape(s: &str, quote: char) -> String {
+            let mut escaped = String::with_capacity(s.len());
+            for c in s.chars() {
+                match c {
+                    '\\' => escaped.push_str("\\\\"),
+                    '\n' => escaped.push_str("\\n"),
+                    '\t' => escaped.push_str("\\t"),
+                    _ if c == quote => {
+                        escaped.push('\\');
+                        escaped.push(c);
+                    }
+                    _ => escaped.push(c),
+                }
+            }
+            escaped
+        }
+
+        match self {
+            Self::Atom(x) => {
+                let is_plain = x.starts_with(|c: char| c.is_ascii_lowercase())
+                    && x.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@');
+                if is_plain {
+                    write!(f, "{x}")
+                } else {
+                    write!(f, "'{}'", escape(x, '\''))
+                }
+            }
+            Self::String(x) => write!(f, "\"{}\"", escape(x, '"')),
+            Self::Char(x) => match x {
+                ' ' => write!(f, "$\\s"),
+                '\\' => write!(f, "$\\\\"),
+                _ if x.is_control() => write!(f, "$\\x{{{:X}}}", *x as u32),
+                _ => write!(f, "${x}"),
+            },
+            Self::Integer(x) => write!(f, "{x}"),
+            Self::Float(x) => {
+                // Erlang requires a fractional part (e.g., `1.0e-7` instead of `1e-7`).
+                let s = format!("{x:?}");
+                match s.split_once('e') {
+                    Some((m, e)) if !m.contains('.') => write!(f, "{m}.0e{e}"),
+                    _ => write!(f, "{s}"),
+                }
+            }
+            Self::Binary(x) => match std::str::from_utf8(x) {
+                Ok(s) if !s.is_empty() && !s.contains(|c: char| c.is_control() && c != '\n') => {
+                    let suffix = if s.is_ascii() { "" } else { "/utf8" };
+                    write!(f, "<<\"{}\"{suffix}>>", escape(s, '"'))
+                }
+                _ => write_items(f, x.iter(), "<<", ">>"),
+            },
+            Self::List(xs) => write_items(f, xs.iter(), "[", "]"),
+            Self::Tuple(xs) => write_items(f, xs.iter(), "{", "}"),
+            Self::Map(xs) => {
+                write_items(f, xs.iter().map(|(k, v)| format!("{k} => {v}")), "#{", "}")
+            }
+        }
+    }
+}
+
+/// A parser of Erlang terms that consist only of literals.
+#[derive(Debug)]
+struct TermParser<'a> {
+    text: &'a str,
+    path: &'a Path,
+    tokens: Vec<LexicalToken>,
+    index: usize,
+}
+
+impl<'a> TermParser<'a> {
+    fn new(text: &'a str, path: &'a Path) -> anyhow::Result<Self> {
+        let mut lexer = erl_tokenize::Lexer::new(text);
+        lexer.set_filepath(path);
+        let tokens = lexer.collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            text,
+            path,
+            tokens,
+            index: 0,
+        })
+    }
+
+    fn is_eof(&self) -> bool {
+        self.index >= self.tokens.len()
+    }
+
+    /// Skips the tokens from `start` to the next `.` (inclusive).
+    fn skip_term(&mut self, start: usize) {
+        self.index = start;
+        while let Ok(token) = self.next() {
+            if token.as_symbol_token().map(|t| t.value()) == Some(Symbol::Dot) {
+                break;
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&LexicalToken> {
+        self.tokens.get(self.index)
+    }
+
+    fn peek_symbol(&self) -> Option<Symbol> {
+        self.peek()
+            .and_then(|t| t.as_symbol_token())
+            .map(|t| t.value())
+    }
+
+    fn next(&mut self) -> anyhow::Result<LexicalToken> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.error(self.text.len(), "unexpected EOF"))?;
+        self.index += 1;
+        Ok(token)
+    }
+
+    fn error(&self, offset: usize, reason: &str) -> anyhow::Error {
+        let position = crate::range::position_at(self.text, offset);
+        anyhow::anyhow!(crate::error::generate_error_message(
+            self.text,
+            Some(self.path),
+            position,
+            reason
+        ))
+    }
+
+    fn token_error(&self, token: &LexicalToken, reason: &str) -> anyhow::Error {
+        self.error(token.start_position().offset(), reason)
+    }
+
+    fn expect_symbol(&mut self, symbol: Symbol) -> anyhow::Result<()> {
+        let token = self.next()?;
+        if token.as_symbol_token().map(|t| t.value()) != Some(symbol) {
+            let expected = format!("expected `{}`", symbol.as_str());
+            return Err(self.token_error(&token, &expected));
+        }
+        Ok(())
+    }
+
+    /// Parses `Item (, Item)* Close` or `Close` (the open delimiter has already been consumed).
+    fn parse_items<T>(
+        &mut self,
+        close: Symbol,
+        mut parse_item: impl FnMut(&mut Self) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Vec<T>> {
+        let mut items = Vec::new();
+        if self.peek_symbol() == Some(close) {
+            self.index += 1;
+            return Ok(items);
+        }
+        loop {
+            items.push(parse_item(self)?);
+            if self.peek_symbol() == Some(Symbol::Comma) {
+                self.index += 1;
+            } else {
+                self.expect_symbol(close)?;
+                return Ok(items);
+            }
+        }
+    }
+
+    fn parse_term(&mut self) -> anyhow::Result<RebarConfigValue> {
+        let token = self.next()?;
+        match &token {
+            LexicalToken::Atom(_)
+                if matches!(self.peek_symbol(), Some(Symbol::OpenParen | Symbol::Colon)) =>
+            {
+                Err(self.token_error(&token, "function calls are not supported"))
+            }
+            LexicalToken::Atom(x) => Ok(RebarConfigValue::Atom(x.value().to_owned())),
+            LexicalToken::Char(x) => Ok(RebarConfigValue::Char(x.value())),
+            LexicalToken::Float(x) => Ok(RebarConfigValue::Float(x.value())),
+            LexicalToken::Integer(x) => Ok(RebarConfigValue::Integer(x.value().clone().into())),
+            LexicalToken::String(x) => {
+                let mut s = x.value().to_owned();
+                while let Some(LexicalToken::String(x)) = self.peek() {
+                    s.push_str(x.value());
+                    self.index += 1;
+                }
+                Ok(RebarConfigValue::String(s))
+            }
+            LexicalToken::Symbol(x) => match x.value() {
+                Symbol::Hyphen | Symbol::Plus => {
+                    let is_negative = x.value() == Symbol::Hyphen;
+                    match self.parse_term()? {
+                        RebarConfigValue::Integer(n) if is_negative => {
+                            Ok(RebarConfigValue::Integer(-n))
+                        }
+                        RebarConfigValue::Float(n) if is_negative => {
+                            Ok(RebarConfigValue::Float(-n))
+                        }
+                        n @ (RebarConfigValue::Integer(_) | RebarConfigValue::Float(_)) => Ok(n),
+                        _ => Err(self.token_error(&token, "expected a number after the sign")),
+                    }
+                }
+                Symbol::OpenSquare => self
+                    .parse_items(Symbol::CloseSquare, Self::parse_term)
+                    .map(RebarConfigValue::List),
+                Symbol::OpenBrace => self
+                    .parse_items(Symbol::CloseBrace, Self::parse_term)
+                    .map(RebarConfigValue::Tuple),
+                Symbol::Sharp => {
+                    self.expect_symbol(Symbol::OpenBrace)?;
+                    self.parse_items(Symbol::CloseBrace, |this| {
+                        let key = this.parse_term()?;
+                        this.expect_symbol(Symbol::DoubleRightArrow)?;
+                        Ok((key, this.parse_term()?))
+                    })
+                    .map(RebarConfigValue::Map)
+                }
+                Symbol::DoubleLeftAngle => {
+                    let segments =
+                        self.parse_items(Symbol::DoubleRightAngle, Self::parse_segment)?;
+                    Ok(RebarConfigValue::Binary(segments.concat()))
+                }
+                _ => Err(self.token_error(&token, "unsupported term")),
+            },
+            _ => Err(self.token_error(&token, "unsupported term")),
+        }
+    }
+
+    /// Parses a segment of a binary (only strings, characters and integers without size specifications are supported).
+    fn parse_segment(&mut self) -> anyhow::Result<Vec<u8>> {
+        let token = self.peek().cloned();
+        let value = self.parse_term()?;
+        let is_utf8 = if self.peek_symbol() == Some(Symbol::Slash) {
+            self.index += 1;
+            let type_token = self.next()?;
+            match type_token.as_atom_token().map(|t| t.value()) {
+                Some("utf8") => true,
+                Some("integer") => false,
+                _ => return Err(self.token_error(&type_token, "unsupported binary segment type")),
+            }
+        } else {
+            false
+        };
+
+        let unsupported = || {
+            let offset = token.as_ref().map_or(0, |t| t.start_position().offset());
+            self.error(offset, "unsupported binary segment")
+        };
+        let chars: Vec<u32> = match value {
+            RebarConfigValue::String(s) => s.chars().map(u32::from).collect(),
+            RebarConfigValue::Char(c) => vec![u32::from(c)],
+            RebarConfigValue::Integer(n) => vec![n.to_u32().ok_or_else(unsupported)?],
+            _ => return Err(unsupported()),
+        };
+        let mut bytes = Vec::new();
+        for c in chars {
+            if is_utf8 {
+                let c = char::from_u32(c).ok_or_else(unsupported)?;
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            } else {
+                // Same as Erlang, integers are truncated to 8 bits.
+                bytes.push(c as u8);
+            }
+        }
+        Ok(bytes)
+    }
+}
+
 pub fn collect_default_target_files() -> anyhow::Result<Vec<PathBuf>> {
     let current_dir = std::env::current_dir()?;
     if is_git_repository(&current_dir) {
@@ -93,14 +570,7 @@ where
         &["ls-files", "--others", "--exclude-standard"][..],
     ];
     for args in args_list {
-        let output = Command::new("git").args(args).output()?;
-        anyhow::ensure!(
-            output.status.success(),
-            "Failed to execute `$ git {}` command.\n{}",
-            Vec::from(args).join(" "),
-            String::from_utf8_lossy(&output.stderr)
-        );
-        for file in String::from_utf8(output.stdout)?.split('\n') {
+        for file in git(args)?.split('\n') {
             if file.is_empty() {
                 continue;
             }
@@ -115,6 +585,132 @@ where
     Ok(files)
 }
 
+/// Collects the target files changed since the given git revision, and the changed line ranges of the files.
+///
+/// Untracked files (except ones ignored by `.gitignore`) are regarded as entirely changed.
+/// For the lines removed without replacement, the lines around the removed ones are regarded as changed.
+pub fn collect_changed_files(since: &str) -> anyhow::Result<Vec<(PathBuf, Vec<FormatRange>)>> {
+    collect_changed_files_in(Path::new("."), since)
+}
+
+fn collect_changed_files_in(
+    dir: &Path,
+    since: &str,
+) -> anyhow::Result<Vec<(PathBuf, Vec<FormatRange>)>> {
+    let diff_args = [
+        "diff",
+        "--no-color",
+        "--no-ext-diff",
+        "--relative",
+        "--diff-filter=AMR",
+    ];
+
+    // The file names are taken from NUL-separated output as `$ git diff` quotes unusual names in its headers.
+    // The diff of each file appears in the same order as the names.
+    let mut files = Vec::new();
+    let names = git_in(
+        dir,
+        &[&diff_args[..], &["--name-only", "-z", since, "--"]].concat(),
+    )?;
+    let names = names.split_terminator('\0').collect::<Vec<_>>();
+    let diff = git_in(
+        dir,
+        &[&diff_args[..], &["--unified=0", since, "--"]].concat(),
+    )?;
+
+    // Changed lines start with `+`, `-`, ` ` or `\`, so they are never taken as diff or hunk headers.
+    let mut file_diffs = Vec::<Vec<&str>>::new();
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") {
+            file_diffs.push(Vec::new());
+        } else if let Some(file_diff) = file_diffs.last_mut() {
+            file_diff.push(line);
+        }
+    }
+    anyhow::ensure!(
+        file_diffs.len() == names.len(),
+        "unexpected `$ git diff` output: {} files were expected but {} diffs were found",
+        names.len(),
+        file_diffs.len()
+    );
+    for (file, file_diff) in names.into_iter().zip(file_diffs) {
+        let path = PathBuf::from(file);
+        if !is_format_target(&path) {
+            continue;
+        }
+
+        let mut ranges = Vec::new();
+        for line in file_diff {
+            if let Some(hunk) = line.strip_prefix("@@ ") {
+                let range = parse_hunk_header(hunk).ok_or_else(|| {
+                    anyhow::anyhow!("unexpected `$ git diff` hunk header for {path:?}: {line:?}")
+                })?;
+                ranges.push(range);
+            }
+        }
+        if !ranges.is_empty() {
+            files.push((path, ranges));
+        }
+    }
+
+    for file in git_in(dir, &["ls-files", "-z", "--others", "--exclude-standard"])?.split('\0') {
+        let path = PathBuf::from(file);
+        if !file.is_empty() && is_format_target(&path) {
+            files.push((
+                path,
+                vec![FormatRange::Lines {
+                    start: 1,
+                    end: usize::MAX,
+                }],
+            ));
+        }
+    }
+    Ok(files)
+}
+
+/// Parses the `-START[,COUNT] +START[,COUNT] @@` part of a unified diff hunk header and
+/// returns the added line range.
+///
+/// If no lines were added (i.e., lines were only removed after the `START` line),
+/// the lines just before and after the removed ones are returned instead.
+fn parse_hunk_header(hunk: &str) -> Option<FormatRange> {
+    let added = hunk.split(' ').nth(1)?.strip_prefix('+')?;
+    let (start, count) = match added.split_once(',') {
+        Some((start, count)) => (start.parse::<usize>().ok()?, count.parse::<usize>().ok()?),
+        None => (added.parse::<usize>().ok()?, 1),
+    };
+    if count == 0 {
+        return Some(FormatRange::Lines {
+            start: start.max(1),
+            end: start + 1,
+        });
+    }
+    Some(FormatRange::Lines {
+        start,
+        end: start + count - 1,
+    })
+}
+
+fn git(args: &[&str]) -> anyhow::Result<String> {
+    git_in(Path::new("."), args)
+}
+
+fn git_in(dir: &Path, args: &[&str]) -> anyhow::Result<String> {
+    // Paths are given as is (e.g., `*` in a file name is not a wildcard).
+    let output = Command::new("git")
+        .arg("--literal-pathspecs")
+        .args(args)
+        .current_dir(dir)
+        .output()?;
+    anyhow::ensure!(
+        output.status.success(),
+        "Failed to execute `$ git {}` command.\n{}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
 fn collect_files_without_git<P: AsRef<Path>, F>(
     root_dir: P,
     is_target: F,
@@ -141,14 +737,9 @@ where
 }
 
 fn is_format_target(path: &Path) -> bool {
-    path.file_name()
-        .and_then(|n| n.to_str())
-        .map_or(false, |n| {
-            n == "rebar.config"
-                || n.ends_with(".erl")
-                || n.ends_with(".hrl")
-                || n.ends_with(".app.src")
-        })
+    path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+        n == "rebar.config" || n.ends_with(".erl") || n.ends_with(".hrl") || n.ends_with(".app.src")
+    })
 }
 
 fn is_git_repository<P: AsRef<Path>>(dir: P) -> bool {
@@ -163,3 +754,201 @@ fn is_git_repository<P: AsRef<Path>>(dir: P) -> bool {
     log::debug!("Found `.git` in {:?}", dir);
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_project_apps_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for app in ["apps/foo", "apps/bar", "lib/baz", "other/qux"] {
+            std::fs::create_dir_all(root.join(app).join("src"))?;
+            std::fs::write(root.join(app).join("src/app.app.src"), "")?;
+        }
+        std::fs::create_dir_all(root.join("apps/not_app"))?;
+        std::fs::write(root.join("rebar.config"), "")?;
+        std::fs::write(root.join("apps/foo/rebar.config"), "")?;
+
+        assert_eq!(
+            find_project_apps(root, &[]),
+            ["apps/bar", "apps/foo", "lib/baz"].map(PathBuf::from)
+        );
+
+        let rebar_config = parse_rebar_config(
+            r#"{project_app_dirs, ["other/*"]}."#.to_owned(),
+            &root.join("rebar.config"),
+        )?;
+        assert_eq!(
+            find_project_apps(root, &rebar_config),
+            [PathBuf::from("other/qux")]
+        );
+
+        // The nearest `rebar.config` belongs to an application of the umbrella project.
+        assert_eq!(
+            find_rebar_config_dir_from(root.join("apps/foo/src")),
+            Some(root.to_path_buf())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn find_build_lib_dirs_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        for profile in ["test", "A+B", "default"] {
+            std::fs::create_dir_all(root.join("_build").join(profile).join("lib"))?;
+        }
+        std::fs::create_dir_all(root.join("_build/no_lib"))?;
+
+        let lib_dir = |profile: &str| root.join("_build").join(profile).join("lib/");
+        assert_eq!(
+            find_build_lib_dirs(root, &["A".to_owned(), "B".to_owned()]),
+            [lib_dir("A+B"), lib_dir("default"), lib_dir("test")]
+        );
+        assert_eq!(
+            find_build_lib_dirs(root, &[]),
+            [lib_dir("default"), lib_dir("A+B"), lib_dir("test")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn eval_rebar_config_script_works() -> anyhow::Result<()> {
+        if Command::new("erl").arg("-version").output().is_err() {
+            eprintln!("skipped: `erl` command is not found");
+            return Ok(());
+        }
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join("rebar.config"),
+            "{erl_opts, [debug_info]}.\n",
+        )?;
+        std::fs::write(
+            dir.path().join("rebar.config.script"),
+            "[{efmt, [{print_width, 80 + 20}]} | CONFIG].\n",
+        )?;
+        let values = eval_rebar_config_script(dir.path())?;
+        assert_eq!(
+            values.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+            ["{efmt, [{print_width, 100}]}", "{erl_opts, [debug_info]}"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_hunk_header_works() {
+        assert_eq!(
+            parse_hunk_header("-3,2 +3,4 @@ foo() ->"),
+            Some(FormatRange::Lines { start: 3, end: 6 })
+        );
+        assert_eq!(
+            parse_hunk_header("-3 +5 @@"),
+            Some(FormatRange::Lines { start: 5, end: 5 })
+        );
+        assert_eq!(
+            parse_hunk_header("-3,2 +2,0 @@"),
+            Some(FormatRange::Lines { start: 2, end: 3 })
+        );
+        assert_eq!(
+            parse_hunk_header("-1 +0,0 @@"),
+            Some(FormatRange::Lines { start: 1, end: 1 })
+        );
+        assert_eq!(parse_hunk_header("foo"), None);
+    }
+
+    #[test]
+    fn collect_changed_files_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        let git = |args: &[&str]| {
+            let config = ["-c", "user.name=efmt", "-c", "user.email=efmt@example.com"];
+            git_in(root, &[&config[..], args].concat())
+        };
+        git(&["init", "--quiet"])?;
+        std::fs::write(root.join("foo.erl"), "foo() ->\n    [a]\n    .\n")?;
+        std::fs::write(root.join("caf\u{e9}.erl"), "bar() -> b.\n")?;
+        std::fs::write(root.join("qux.erl"), "qux() ->\n    a,\n    b.\n")?;
+        std::fs::write(root.join("README"), "foo\n")?;
+        git(&["add", "."])?;
+        git(&["commit", "--quiet", "-m", "init"])?;
+
+        // An added line that starts with `++ ` looks like a `+++ ` header in the diff.
+        std::fs::write(root.join("foo.erl"), "foo() ->\n    [a]\n    ++ [b].\n")?;
+        // A non-ASCII name is quoted in the diff headers.
+        std::fs::write(root.join("caf\u{e9}.erl"), "bar() -> c.\n")?;
+        std::fs::write(root.join("new.erl"), "baz() -> c.\n")?;
+        // Lines are only removed.
+        std::fs::write(root.join("qux.erl"), "qux() ->\n    b.\n")?;
+        std::fs::write(root.join("README"), "bar\n")?;
+
+        let mut files = collect_changed_files_in(root, "HEAD")?;
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        let lines = |start, end| vec![FormatRange::Lines { start, end }];
+        assert_eq!(
+            files,
+            [
+                (PathBuf::from("caf\u{e9}.erl"), lines(1, 1)),
+                (PathBuf::from("foo.erl"), lines(3, 3)),
+                (PathBuf::from("new.erl"), lines(1, usize::MAX)),
+                (PathBuf::from("qux.erl"), lines(1, 2)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn load_rebar_config_works() -> anyhow::Result<()> {
+        use RebarConfigValue::*;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("rebar.config");
+        std::fs::write(
+            &path,
+            indoc::indoc! {r#"
+                {efmt, [{exclude_file, <<"^test/data/">>},
+                        {print_width, 100}]}.
+                {foo, [-1, +2.5, -1.0e-3, 123456789012345678901234567890, $a, "foo" "bar",
+                       #{a => <<1, 2, "ab">>, "k" => <<"\x{3042}"/utf8>>}, #{}, {}, []]}.
+            "#},
+        )?;
+        let values = load_rebar_config(&path)?;
+        assert_eq!(values.len(), 2);
+        assert_eq!(
+            values[0].to_string(),
+            r#"{efmt, [{exclude_file, <<"^test/data/">>}, {print_width, 100}]}"#
+        );
+        let Some(("foo", List(items))) = values[1].as_kv_tuple() else {
+            panic!("unexpected value: {}", values[1]);
+        };
+        assert_eq!(items[0], Integer((-1).into()));
+        assert_eq!(items[1], Float(2.5));
+        assert_eq!(items[2], Float(-0.001));
+        assert_eq!(items[3].to_string(), "123456789012345678901234567890");
+        assert_eq!(items[4], Char('a'));
+        assert_eq!(items[5].as_str(), Some("foobar"));
+        assert_eq!(
+            items[6],
+            Map(vec![
+                (Atom("a".to_owned()), Binary(vec![1, 2, b'a', b'b'])),
+                (
+                    String("k".to_owned()),
+                    Binary("\u{3042}".as_bytes().to_vec())
+                ),
+            ])
+        );
+        assert_eq!(items[7].to_string(), "#{}");
+
+        // Unsupported terms are skipped.
+        std::fs::write(
+            &path,
+            "{foo, [bar, baz()]}.\n{a, [b | c]}.\n{d, <<1:16>>}.\n{e, f. {g, h}.\n",
+        )?;
+        let values = load_rebar_config(&path)?;
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0].to_string(), "{g, h}");
+        Ok(())
+    }
+}
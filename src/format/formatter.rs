@@ -228,6 +228,11 @@ impl Formatter {
         &mut self.ts
     }
 
+    /// Makes the formatter ignore the text (including comments and macros) before `position`.
+    pub(crate) fn set_next_position(&mut self, position: Position) {
+        self.next_position = position;
+    }
+
     pub fn format(mut self, max_columns: usize) -> String {
         self.add_macros_and_comments(Position::new(usize::MAX - 1, usize::MAX, usize::MAX));
         ItemWriter::new(&self.ts.text(), max_columns).write_to_string(&self.item)
@@ -0,0 +1,202 @@
+//! A persistent cache of the files that are known to be formatted correctly.
+use crate::parse::include::{content_hash, IncludeDependencies};
+use crate::parse::{FsIncludeSource, IncludeSource};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub(crate) const CACHE_DIR_NAME: &str = "formatted";
+pub(crate) const CACHE_FORMAT_VERISON: &str = "v1";
+
+/// A cache entry that exists only if the target file is formatted correctly.
+///
+/// The entry is looked up by a key made from the content of the target file, the version of efmt and
+/// the formatting options.
+/// So, only the files included by the target file (and the paths of the unresolved includes) need to be checked
+/// to validate the entry.
+#[derive(Debug)]
+pub(crate) struct FormatCache {
+    cache_path: PathBuf,
+    include_source: Arc<dyn IncludeSource>,
+}
+
+impl FormatCache {
+    pub(crate) fn new(
+        cache_dir: &Path,
+        path: &Path,
+        text: &str,
+        options_fingerprint: &str,
+        include_source: Arc<dyn IncludeSource>,
+    ) -> Self {
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let key = sha256::digest(format!(
+            "{}\n{}\n{}\n{}",
+            env!("CARGO_PKG_VERSION"),
+            path.display(),
+            options_fingerprint,
+            sha256::digest(text)
+        ));
+        Self {
+            cache_path: cache_dir
+                .join(CACHE_DIR_NAME)
+                .join(CACHE_FORMAT_VERISON)
+                .join(key),
+            include_source,
+        }
+    }
+
+    pub(crate) fn is_formatted(&self) -> bool {
+        let Ok(data) = std::fs::read_to_string(&self.cache_path) else {
+            return false;
+        };
+        let entry: CacheEntry = match serde_json::from_str(&data) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!(
+                    "Failed to load format cache file {:?}: {}",
+                    self.cache_path,
+                    e
+                );
+                return false;
+            }
+        };
+
+        if let Some(path) = entry.find_modified_file(self.include_source.as_ref()) {
+            log::debug!(
+                "The include file {:?} has been modified, created or removed since the time {:?} was cached.",
+                path,
+                entry.path
+            );
+            let _ = std::fs::remove_file(&self.cache_path);
+            return false;
+        }
+        true
+    }
+
+    pub(crate) fn save(&self, path: &Path, dependencies: &IncludeDependencies) {
+        if dependencies.untracked {
+            log::debug!(
+                "Skipped saving the format cache for {:?} as it has an untracked include.",
+                path
+            );
+            return;
+        }
+
+        let mut entry = CacheEntry {
+            path: path.to_path_buf(),
+            included_files: Vec::new(),
+        };
+        for file in &dependencies.files {
+            let Some(hash) = content_hash(self.include_source.as_ref(), file) else {
+                log::debug!("Failed to read the include file {:?}", file);
+                return;
+            };
+            entry.included_files.push((file.clone(), Some(hash)));
+        }
+        for file in &dependencies.missing_files {
+            entry.included_files.push((file.clone(), None));
+        }
+
+        let result = (|| -> anyhow::Result<()> {
+            let dir = self.cache_path.parent().expect("unreachable");
+            std::fs::create_dir_all(dir)?;
+            let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+            serde_json::to_writer(std::io::BufWriter::new(&mut temp), &entry)?;
+            temp.persist(&self.cache_path)?;
+            Ok(())
+        })();
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to save the format cache entry for {:?}: {}",
+                path,
+                e
+            );
+        } else {
+            log::debug!(
+                "Saved a format cache for {:?} into {:?}",
+                path,
+                self.cache_path
+            );
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct CacheEntry {
+    path: PathBuf,
+
+    // The SHA-256 hashes of the contents of the files included by the target file (directly or indirectly).
+    // `None` means that the file did not exist (e.g., a candidate path of an unresolved include).
+    included_files: Vec<(PathBuf, Option<String>)>,
+}
+
+impl CacheEntry {
+    /// Returns the first included file that has been modified, created or removed since the entry was created.
+    fn find_modified_file(&self, source: &dyn IncludeSource) -> Option<&Path> {
+        self.included_files
+            .iter()
+            .find(|(path, hash)| content_hash(source, path) != *hash)
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Returns `true` if the target file or any of the included files has been removed or modified.
+    pub(crate) fn is_stale(&self) -> bool {
+        !self.path.exists() || self.find_modified_file(&FsIncludeSource).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::items::Module;
+
+    #[test]
+    fn format_cache_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let cache_dir = dir.path().join("cache");
+        let header = dir.path().join("foo.hrl");
+        let nested_header = dir.path().join("bar.hrl");
+        let file = dir.path().join("foo.erl");
+        std::fs::write(&header, "-include(\"bar.hrl\").\n-define(FOO, foo).\n")?;
+        std::fs::write(&nested_header, "-define(BAR, bar).\n")?;
+        std::fs::write(&file, "-include(\"foo.hrl\").\n\n\nfoo() ->\n    ?FOO.\n")?;
+        let text = std::fs::read_to_string(&file)?;
+
+        let options = crate::Options::new().format_cache_dir(&cache_dir);
+        let fingerprint = format!(
+            "{}\n{}",
+            std::any::type_name::<Module>(),
+            options.fingerprint()
+        );
+        let source: Arc<dyn IncludeSource> = Arc::new(crate::parse::FsIncludeSource);
+        let cache = FormatCache::new(&cache_dir, &file, &text, &fingerprint, source.clone());
+        assert!(!cache.is_formatted());
+
+        assert_eq!(options.clone().format_file::<Module, _>(&file)?, text);
+        assert!(cache.is_formatted());
+
+        // Changing the options invalidates the cache.
+        let other = FormatCache::new(&cache_dir, &file, &text, "other", source);
+        assert!(!other.is_formatted());
+
+        // Rewriting an included file with the same content keeps the cache.
+        std::fs::write(&header, std::fs::read_to_string(&header)?)?;
+        assert!(cache.is_formatted());
+
+        // Modifying an indirectly included file invalidates the cache.
+        std::fs::write(&nested_header, "-define(BAR, baz).\n")?;
+        assert!(!cache.is_formatted());
+        assert_eq!(options.clone().format_file::<Module, _>(&file)?, text);
+        assert!(cache.is_formatted());
+
+        // Removing the included file invalidates the cache.
+        std::fs::remove_file(&header)?;
+        assert!(!cache.is_formatted());
+
+        // Creating the file of an unresolved include invalidates the cache.
+        assert_eq!(options.clone().format_file::<Module, _>(&file)?, text);
+        assert!(cache.is_formatted());
+        std::fs::write(&header, "-define(FOO, foo).\n")?;
+        assert!(!cache.is_formatted());
+        Ok(())
+    }
+}
@@ -1,8 +1,7 @@
 use crate::format::Format;
 use crate::items::components::{Either, Element};
-use crate::items::expressions::{BaseExpr, FullExpr, ListExpr, LiteralExpr};
-use crate::items::tokens::AtomToken;
-use crate::parse::Parse;
+use crate::items::expressions::{BaseExpr, FullExpr, LiteralExpr};
+use crate::parse::{Parse, TokenStream};
 use crate::span::Span;
 
 pub mod expressions;
@@ -28,20 +27,38 @@ pub use self::module::Module;
 pub struct ModuleOrConfig(Either<Module, Config>);
 
 /// One of [forms].
-#[derive(Debug, Clone, Span, Parse, Format)]
-pub struct Form(self::forms::Form);
+#[derive(Debug, Clone, Span, Format)]
+pub struct Form(Either<self::forms::Form, self::forms::SkippedForm>);
 
 impl Form {
-    fn get(&self) -> &self::forms::Form {
-        &self.0
+    pub(crate) fn skipped(form: self::forms::SkippedForm) -> Self {
+        Self(Either::B(form))
+    }
+
+    fn get(&self) -> Option<&self::forms::Form> {
+        if let Either::A(x) = &self.0 {
+            Some(x)
+        } else {
+            None
+        }
     }
 
     pub(crate) fn is_func_spec(&self) -> bool {
-        matches!(self.0, self::forms::Form::FunSpec(_))
+        matches!(self.get(), Some(self::forms::Form::FunSpec(_)))
+    }
+
+    pub(crate) fn is_func_doc(&self) -> bool {
+        matches!(self.get(), Some(self::forms::Form::Attr(x)) if x.is_doc())
     }
 
     pub(crate) fn is_func_decl(&self) -> bool {
-        matches!(self.0, self::forms::Form::FunDecl(_))
+        matches!(self.get(), Some(self::forms::Form::FunDecl(_)))
+    }
+}
+
+impl Parse for Form {
+    fn parse(ts: &mut TokenStream) -> crate::parse::Result<Self> {
+        ts.parse().map(|x| Self(Either::A(x)))
     }
 }
 
@@ -79,38 +96,4 @@ impl Expr {
             None
         }
     }
-
-    pub(crate) fn as_string(&self) -> Option<&str> {
-        if let FullExpr::Base(BaseExpr::Literal(LiteralExpr::String(x))) = &self.0 {
-            if x.tokens().len() == 1 {
-                return Some(x.tokens()[0].value());
-            }
-        }
-        None
-    }
-
-    pub(crate) fn as_u32(&self, text: &str) -> Option<u32> {
-        if let FullExpr::Base(BaseExpr::Literal(LiteralExpr::Integer(x))) = &self.0 {
-            if let Ok(x) = text[x.start_position().offset()..x.end_position().offset()].parse() {
-                return Some(x);
-            }
-        }
-        None
-    }
-
-    pub(crate) fn as_list(&self) -> Option<&[Expr]> {
-        if let FullExpr::Base(BaseExpr::List(x)) = &self.0 {
-            if let ListExpr::Construct(x) = &**x {
-                return Some(x.items());
-            }
-        }
-        None
-    }
-
-    pub(crate) fn as_tuple(&self) -> Option<(Option<&AtomToken>, &[Expr])> {
-        if let FullExpr::Base(BaseExpr::Tuple(x)) = &self.0 {
-            return Some(x.items());
-        }
-        None
-    }
 }
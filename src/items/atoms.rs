@@ -58,3 +58,32 @@ impl_parse!(ExportAtom, "export");
 #[derive(Debug, Clone, Span, Format, Element)]
 pub struct ExportTypeAtom(AtomToken);
 impl_parse!(ExportTypeAtom, "export_type");
+
+#[derive(Debug, Clone, Span, Format, Element)]
+pub struct UndefAtom(AtomToken);
+impl_parse!(UndefAtom, "undef");
+
+#[derive(Debug, Clone, Span, Format, Element)]
+pub struct IfdefAtom(AtomToken);
+impl_parse!(IfdefAtom, "ifdef");
+
+#[derive(Debug, Clone, Span, Format, Element)]
+pub struct IfndefAtom(AtomToken);
+impl_parse!(IfndefAtom, "ifndef");
+
+#[derive(Debug, Clone, Span, Format, Element)]
+pub struct ElifAtom(AtomToken);
+impl_parse!(ElifAtom, "elif");
+
+#[derive(Debug, Clone, Span, Format, Element)]
+pub struct EndifAtom(AtomToken);
+impl_parse!(EndifAtom, "endif");
+
+/// `else` that is not a keyword (i.e., the `maybe_expr` feature is disabled).
+#[derive(Debug, Clone, Span, Format)]
+pub struct ElseAtom(AtomToken);
+impl_parse!(ElseAtom, "else");
+
+#[derive(Debug, Clone, Span, Format)]
+pub struct FeatureAtom(AtomToken);
+impl_parse!(FeatureAtom, "feature");
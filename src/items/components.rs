@@ -90,7 +90,7 @@ impl<T> Maybe<T> {
 
 impl<T: Element> Element for Maybe<T> {
     fn is_packable(&self) -> bool {
-        self.get().map_or(true, Element::is_packable)
+        self.get().is_none_or(Element::is_packable)
     }
 }
 
@@ -247,12 +247,6 @@ impl<T, D> Items<T, D> {
 #[derive(Debug, Clone, Span, Parse)]
 struct MaybePackedItems<T, D = CommaDelimiter>(Items<T, D>);
 
-impl<T, D> MaybePackedItems<T, D> {
-    pub(crate) fn items(&self) -> &[T] {
-        self.0.items()
-    }
-}
-
 impl<T: Format, D: Format> MaybePackedItems<T, D> {
     fn packed_format(&self, fmt: &mut Formatter) {
         fmt.subregion(Indent::CurrentColumn, Newline::Never, |fmt| {
@@ -298,12 +292,6 @@ pub struct ListLike<T: Element, D = CommaDelimiter> {
     close: CloseSquareSymbol,
 }
 
-impl<T: Element, D> ListLike<T, D> {
-    pub(crate) fn items(&self) -> &[T] {
-        self.items.items()
-    }
-}
-
 #[derive(Debug, Clone, Span, Parse, Format)]
 pub struct TupleLike<T: Element> {
     open: OpenBraceSymbol,
@@ -312,12 +300,6 @@ pub struct TupleLike<T: Element> {
     close: CloseBraceSymbol,
 }
 
-impl<T: Element> TupleLike<T> {
-    pub(crate) fn items(&self) -> (Option<&AtomToken>, &[T]) {
-        (self.tag.get().map(|(x, _)| x), self.items.items())
-    }
-}
-
 #[derive(Debug, Clone, Span, Parse, Format)]
 pub struct BitstringLike<T: Element> {
     open: DoubleLeftAngleSymbol,
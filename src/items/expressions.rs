@@ -33,7 +33,7 @@ pub use self::blocks::{BeginExpr, CaseExpr, CatchExpr, IfExpr, ReceiveExpr, TryE
 pub use self::calls::{BinaryOpCallExpr, FunctionCallExpr, UnaryOpCallExpr};
 pub use self::functions::{AnonymousFunctionExpr, DefinedFunctionExpr, NamedFunctionExpr};
 pub use self::lists::{ListComprehensionExpr, ListConstructExpr};
-pub use self::maps::{MapConstructExpr, MapUpdateExpr};
+pub use self::maps::{MapComprehensionExpr, MapConstructExpr, MapUpdateExpr};
 pub use self::records::{RecordAccessExpr, RecordConstructExpr, RecordIndexExpr, RecordUpdateExpr};
 pub use self::strings::StringExpr;
 pub use self::tuples::TupleExpr;
@@ -43,6 +43,7 @@ pub(crate) enum BaseExpr {
     List(Box<ListExpr>),
     Tuple(Box<TupleExpr>),
     MapConstruct(Box<MapConstructExpr>),
+    MapComprehension(Box<MapComprehensionExpr>),
     RecordConstructOrIndex(Box<RecordConstructOrIndexExpr>),
     Bitstring(Box<BitstringExpr>),
     Function(Box<FunctionExpr>),
@@ -66,7 +67,13 @@ impl Parse for BaseExpr {
                 Symbol::OpenParen => ts.parse().map(Self::Parenthesized),
                 Symbol::Sharp => {
                     if ts.peek::<(LexicalToken, OpenBraceSymbol)>().is_some() {
-                        ts.parse().map(Self::MapConstruct)
+                        if let Ok(x) = ts.parse() {
+                            Ok(Self::MapConstruct(x))
+                        } else if let Ok(x) = ts.parse() {
+                            Ok(Self::MapComprehension(x))
+                        } else {
+                            Err(ts.take_last_error().expect("unreachable"))
+                        }
                     } else {
                         ts.parse().map(Self::RecordConstructOrIndex)
                     }
@@ -1,10 +1,11 @@
 use crate::format::{Format, Formatter, Indent, Newline};
 use crate::items::components::{
-    BinaryOpStyle, Either, NonEmptyItems, Params, WithArrow, WithGuard,
+    BinaryOpStyle, Either, MapItem, NonEmptyItems, Params, WithArrow, WithGuard,
 };
 use crate::items::keywords;
 use crate::items::symbols::{
-    self, CommaSymbol, DoubleLeftArrowSymbol, DoubleVerticalBarSymbol, LeftArrowSymbol,
+    self, CommaSymbol, DoubleAmpersandSymbol, DoubleLeftArrowSymbol, DoubleVerticalBarSymbol,
+    LeftArrowSymbol, StrictDoubleLeftArrowSymbol, StrictLeftArrowSymbol,
 };
 use crate::items::tokens::LexicalToken;
 use crate::items::Expr;
@@ -56,14 +57,27 @@ impl<const OFFSET: usize> Format for Body<OFFSET> {
 }
 
 /// ((`$GENERATOR` | `$FILTER`) `,`?)+
-/// - $GENERATOR: `Expr` (`<-` | `<=`) `Expr`
+/// - $GENERATOR: `$PATTERN` (`<-` | `<=` | `<:-` | `<:=`) `Expr` (`&&` `$GENERATOR`)?
+/// - $PATTERN: `Expr` | `Expr` `:=` `Expr`
 /// - $FILTER: `Expr`
 #[derive(Debug, Clone, Span, Parse, Format)]
-pub struct Qualifier(Either<Generator, Expr>);
+pub struct Qualifier(Either<NonEmptyItems<Generator, ZipDelimiter>, Expr>);
+
+#[derive(Debug, Clone, Span, Parse)]
+struct ZipDelimiter(DoubleAmpersandSymbol);
+
+impl Format for ZipDelimiter {
+    fn format(&self, fmt: &mut Formatter) {
+        fmt.add_space();
+        self.0.format(fmt);
+        fmt.add_space();
+    }
+}
 
 #[derive(Debug, Clone, Span, Parse)]
 struct Generator {
-    pattern: Expr,
+    // The first one is for map generators (e.g., `K := V <- Map`).
+    pattern: Either<MapItem<Expr>, Expr>,
     delimiter: GeneratorDelimiter,
     sequence: Expr,
 }
@@ -85,18 +99,23 @@ impl Format for Generator {
 }
 
 #[derive(Debug, Clone, Span, Parse, Format)]
-struct GeneratorDelimiter(Either<LeftArrowSymbol, DoubleLeftArrowSymbol>);
+enum GeneratorDelimiter {
+    Relaxed(LeftArrowSymbol),
+    RelaxedBinary(DoubleLeftArrowSymbol),
+    Strict(StrictLeftArrowSymbol),
+    StrictBinary(StrictDoubleLeftArrowSymbol),
+}
 
 #[derive(Debug, Clone, Span, Parse)]
-pub(crate) struct ComprehensionExpr<Open, Close> {
+pub(crate) struct ComprehensionExpr<Open, Close, Value = Expr> {
     open: Open,
-    value: Expr,
+    value: Value,
     delimiter: DoubleVerticalBarSymbol,
     qualifiers: NonEmptyItems<Qualifier>,
     close: Close,
 }
 
-impl<Open: Format, Close: Format> Format for ComprehensionExpr<Open, Close> {
+impl<Open: Format, Close: Format, Value: Format> Format for ComprehensionExpr<Open, Close, Value> {
     fn format(&self, fmt: &mut Formatter) {
         fmt.subregion(Indent::CurrentColumn, Newline::Never, |fmt| {
             self.open.format(fmt);
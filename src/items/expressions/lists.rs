@@ -19,12 +19,6 @@ pub enum ListExpr {
 #[derive(Debug, Clone, Span, Parse, Format)]
 pub struct ListConstructExpr(ListLike<Expr, ListItemDelimiter>);
 
-impl ListConstructExpr {
-    pub(crate) fn items(&self) -> &[Expr] {
-        self.0.items()
-    }
-}
-
 #[derive(Debug, Clone, Span, Parse)]
 enum ListItemDelimiter {
     Comma(CommaSymbol),
@@ -120,6 +114,26 @@ mod tests {
                        4, 5],
                  Y <= Z,
                  false ]"},
+            indoc::indoc! {"
+            %---10---|%---20---|
+            [ X || X <:- L ]"},
+            indoc::indoc! {"
+            %---10---|%---20---|
+            [ {X, Y}
+              || <<X>> <:= B,
+                 Y <:- L ]"},
+            indoc::indoc! {"
+            %---10---|%---20---|
+            [ {X, Y}
+              || X <- L &&
+                 Y <- M ]"},
+            indoc::indoc! {"
+            %---10---|%---20---|
+            [ {X, Y, Z}
+              || X <- L &&
+                 Y <- M &&
+                 Z <- N,
+                 X > 0 ]"},
         ];
         for text in texts {
             crate::assert_format!(text, Expr);
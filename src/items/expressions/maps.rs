@@ -1,6 +1,9 @@
 use crate::format::Format;
-use crate::items::components::MapLike;
-use crate::items::symbols::SharpSymbol;
+use crate::items::components::{MapItem, MapLike};
+use crate::items::expressions::components::ComprehensionExpr;
+#[cfg(doc)]
+use crate::items::expressions::components::Qualifier;
+use crate::items::symbols::{CloseBraceSymbol, OpenBraceSymbol, SharpSymbol};
 use crate::items::Expr;
 use crate::parse::{self, Parse, ResumeParse};
 use crate::span::Span;
@@ -11,6 +14,12 @@ use crate::span::Span;
 #[derive(Debug, Clone, Span, Parse, Format)]
 pub struct MapConstructExpr(MapLike<SharpSymbol, Expr>);
 
+/// `#` `{` [Expr] `=>` [Expr] `||` ([Qualifier] `,`?)+ `}`
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub struct MapComprehensionExpr(
+    ComprehensionExpr<(SharpSymbol, OpenBraceSymbol), CloseBraceSymbol, MapItem<Expr>>,
+);
+
 /// `$VALUE` `#` `{` (`$ENTRY`, `,`?)* `}`
 ///
 /// - $VALUE: `Expr`
@@ -52,6 +61,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn map_comprehension_works() {
+        let texts = [
+            "#{ K => V || V }",
+            indoc::indoc! {"
+            %---10---|%---20---|
+            #{ K => V * 2
+               || K := V
+                      <- Map }"},
+            indoc::indoc! {"
+            %---10---|%---20---|
+            #{ K => V
+               || K := V <- M,
+                  V > 0 }"},
+            indoc::indoc! {"
+            %---10---|%---20---|
+            #{ X => true
+               || X <- [1, 2, 3,
+                        4, 5] }"},
+            indoc::indoc! {"
+            %---10---|%---20---|
+            [ {K, V}
+              || K := V <- M ]"},
+        ];
+        for text in texts {
+            crate::assert_format!(text, Expr);
+        }
+    }
+
     #[test]
     fn map_update_works() {
         let texts = [
@@ -8,12 +8,6 @@ use crate::span::{Position, Span};
 #[derive(Debug, Clone)]
 pub struct StringExpr(Vec<StringToken>);
 
-impl StringExpr {
-    pub(crate) fn tokens(&self) -> &[StringToken] {
-        &self.0
-    }
-}
-
 impl Span for StringExpr {
     fn start_position(&self) -> Position {
         self.0[0].start_position()
@@ -49,7 +43,8 @@ impl Format for StringExpr {
 
 impl Element for StringExpr {
     fn is_packable(&self) -> bool {
-        self.0.len() == 1
+        // Multi-line strings such as triple-quoted ones are not packable.
+        self.0.len() == 1 && self.start_position().line() == self.end_position().line()
     }
 }
 
@@ -70,6 +65,18 @@ mod tests {
             foo("bar"
                 "baz",
                 qux)"#},
+            indoc::indoc! {r#"
+            foo("""
+                  bar
+                    "baz"
+                  """)"#},
+            indoc::indoc! {r#"
+            [~"foo\"",
+             ~B(b\),
+             ~s{x}suffix,
+             ~b"""
+                 bar
+              """]"#},
         ];
         for text in texts {
             crate::assert_format!(text, Expr);
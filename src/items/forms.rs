@@ -1,8 +1,9 @@
 //! Erlang top-level components such as attributes, directives or declarations.
 use crate::format::{Format, Formatter, Indent, Newline};
 use crate::items::atoms::{
-    CallbackAtom, DefineAtom, ExportAtom, ExportTypeAtom, IncludeAtom, IncludeLibAtom, OpaqueAtom,
-    RecordAtom, SpecAtom, TypeAtom,
+    CallbackAtom, DefineAtom, ElifAtom, ElseAtom, EndifAtom, ExportAtom, ExportTypeAtom,
+    FeatureAtom, IfdefAtom, IfndefAtom, IncludeAtom, IncludeLibAtom, OpaqueAtom, RecordAtom,
+    SpecAtom, TypeAtom, UndefAtom,
 };
 use crate::items::components::{
     Clauses, CommaDelimiter, Either, Element, Items, Maybe, Never, NonEmptyItems, Null, Params,
@@ -18,14 +19,18 @@ use crate::items::symbols::{
 use crate::items::tokens::{AtomToken, IntegerToken, LexicalToken, StringToken, VariableToken};
 use crate::items::Expr;
 use crate::items::Type;
-use crate::parse::Parse;
-use crate::span::Span;
+use crate::parse::{self, IncludeOptions, Parse, TokenStream};
+use crate::span::{Position, Span};
+use erl_tokenize::values::Symbol;
 use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Span, Parse, Format)]
 pub(super) enum Form {
     Define(DefineDirective),
     Include(IncludeDirective),
+    Undef(UndefDirective),
+    Conditional(Box<ConditionalBlock>),
+    ConditionalDirective(ConditionalDirective),
     FunSpec(FunSpec),
     FunDecl(FunDecl),
     TypeDecl(TypeDecl),
@@ -235,13 +240,19 @@ struct ExportItem {
 
 /// `-` `$NAME` `$ARGS`? `.`
 ///
-/// - $NAME: [AtomToken] | `if`
+/// - $NAME: [AtomToken]
 /// - $ARGS: `(` (`$ARG` `,`?)* `)`
 /// - $ARG: [Expr]
 #[derive(Debug, Clone, Span, Parse, Format)]
-pub struct Attr(AttrLike<AttrName, AttrValue, Null>);
+pub struct Attr(AttrLike<AtomToken, AttrValue, Null>);
+
+impl Attr {
+    /// Returns `true` if this is a `-doc` attribute (i.e., the documentation of the following function).
+    pub(crate) fn is_doc(&self) -> bool {
+        self.0.name().value() == "doc"
+    }
+}
 
-type AttrName = Either<AtomToken, Either<IfKeyword, ElseKeyword>>;
 type AttrValue = NonEmptyItems<Expr>;
 
 #[derive(Debug, Clone, Span, Parse)]
@@ -252,6 +263,20 @@ struct AttrLike<Name, Value, Empty = Never> {
     dot: DotSymbol,
 }
 
+impl<Name, Value, Empty> AttrLike<Name, Value, Empty> {
+    fn name(&self) -> &Name {
+        &self.name
+    }
+
+    fn value(&self) -> Option<&Value> {
+        match &self.value {
+            Either::A(x) => Some(x.get()),
+            Either::B(Either::A(x)) => Some(x),
+            Either::B(Either::B(_)) => None,
+        }
+    }
+}
+
 impl<Name: Format, Value: Format, Empty: Format> Format for AttrLike<Name, Value, Empty> {
     fn format(&self, fmt: &mut Formatter) {
         self.hyphen.format(fmt);
@@ -323,6 +348,212 @@ impl Format for DefineDirective {
     }
 }
 
+/// `-` `undef` `(` `$NAME` `)` `.`
+///
+/// - $NAME: [AtomToken] | [VariableToken]
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub struct UndefDirective(AttrLike<UndefAtom, MacroName>);
+
+impl UndefDirective {
+    pub fn macro_name(&self) -> &str {
+        self.0.value().expect("unreachable").value()
+    }
+}
+
+/// `-` (`ifdef` | `ifndef`) `(` `$NAME` `)` `.`
+///
+/// - $NAME: [AtomToken] | [VariableToken]
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub struct IfdefDirective(AttrLike<Either<IfdefAtom, IfndefAtom>, MacroName>);
+
+impl IfdefDirective {
+    pub fn macro_name(&self) -> &str {
+        self.0.value().expect("unreachable").value()
+    }
+
+    pub fn is_ifndef(&self) -> bool {
+        matches!(self.0.name(), Either::B(_))
+    }
+}
+
+/// `-` `if` `(` `$CONDITION` `)` `.`
+///
+/// - $CONDITION: [Expr]
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub struct IfDirective(AttrLike<IfKeyword, Expr>);
+
+impl IfDirective {
+    /// Returns the value of the condition if it can be evaluated statically (i.e., `true` or `false` atom).
+    pub fn condition(&self) -> Option<bool> {
+        static_condition(self.0.value())
+    }
+}
+
+/// `-` `elif` `(` `$CONDITION` `)` `.`
+///
+/// - $CONDITION: [Expr]
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub struct ElifDirective(AttrLike<ElifAtom, Expr>);
+
+impl ElifDirective {
+    /// Returns the value of the condition if it can be evaluated statically (i.e., `true` or `false` atom).
+    pub fn condition(&self) -> Option<bool> {
+        static_condition(self.0.value())
+    }
+}
+
+fn static_condition(condition: Option<&Expr>) -> Option<bool> {
+    match condition.and_then(|x| x.as_atom()) {
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        _ => None,
+    }
+}
+
+/// `-` `else` `.`
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub struct ElseDirective(AttrLike<Either<ElseKeyword, ElseAtom>, Never, Null>);
+
+/// `-` `endif` `.`
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub struct EndifDirective(AttrLike<EndifAtom, Never, Null>);
+
+/// One of the directives that make up a [ConditionalBlock].
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub enum ConditionalDirective {
+    Ifdef(IfdefDirective),
+    If(IfDirective),
+    Elif(ElifDirective),
+    Else(ElseDirective),
+    Endif(EndifDirective),
+}
+
+/// `$OPEN` [Form]* (`$ELIF` [Form]*)* (`$ELSE` [Form]*)? `$ENDIF`
+///
+/// - $OPEN: [IfdefDirective] | [IfDirective]
+/// - $ELIF: [ElifDirective]
+/// - $ELSE: [ElseDirective]
+/// - $ENDIF: [EndifDirective]
+///
+/// [Form]: crate::items::Form
+#[derive(Debug, Clone)]
+pub struct ConditionalBlock {
+    branches: Vec<ConditionalBranch>,
+    endif: EndifDirective,
+}
+
+impl ConditionalBlock {
+    /// Returns the directive and the forms of each branch in this block.
+    pub(crate) fn branches(
+        &self,
+    ) -> impl Iterator<Item = (&ConditionalDirective, &[crate::items::Form])> {
+        self.branches
+            .iter()
+            .map(|branch| (&branch.directive, branch.forms.as_slice()))
+    }
+
+    pub(crate) fn endif(&self) -> &EndifDirective {
+        &self.endif
+    }
+}
+
+impl Span for ConditionalBlock {
+    fn start_position(&self) -> Position {
+        self.branches[0].directive.start_position()
+    }
+
+    fn end_position(&self) -> Position {
+        self.endif.end_position()
+    }
+}
+
+impl Parse for ConditionalBlock {
+    fn parse(ts: &mut TokenStream) -> parse::Result<Self> {
+        let directive = match ts.parse()? {
+            Either::A(x) => ConditionalDirective::Ifdef(x),
+            Either::B(x) => ConditionalDirective::If(x),
+        };
+        let mut branches = vec![ConditionalBranch {
+            directive,
+            forms: Vec::new(),
+        }];
+        loop {
+            if let Ok(endif) = ts.parse() {
+                return Ok(Self { branches, endif });
+            }
+
+            let in_else = matches!(
+                branches.last().map(|x| &x.directive),
+                Some(ConditionalDirective::Else(_))
+            );
+            if !in_else {
+                let directive = if let Ok(x) = ts.parse() {
+                    Some(ConditionalDirective::Elif(x))
+                } else {
+                    ts.parse().ok().map(ConditionalDirective::Else)
+                };
+                if let Some(directive) = directive {
+                    branches.push(ConditionalBranch {
+                        directive,
+                        forms: Vec::new(),
+                    });
+                    continue;
+                }
+            }
+
+            let form = ts.parse()?;
+            branches.last_mut().expect("unreachable").forms.push(form);
+        }
+    }
+}
+
+impl Format for ConditionalBlock {
+    fn format(&self, fmt: &mut Formatter) {
+        super::module::format_conditional_block(fmt, self);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ConditionalBranch {
+    directive: ConditionalDirective,
+    forms: Vec<crate::items::Form>,
+}
+
+/// `-` `feature` `(` `$NAME` `,` (`enable` | `disable`) `)` `.`
+///
+/// - $NAME: [AtomToken]
+///
+/// Note that this is only used to track the enabled features (a `-feature` attribute in a module is parsed as [Attr]).
+#[derive(Debug, Clone, Span, Parse, Format)]
+pub(crate) struct FeatureDirective(AttrLike<FeatureAtom, FeatureDirectiveValue>);
+
+impl FeatureDirective {
+    pub fn name(&self) -> &str {
+        self.0.value().map_or("", |x| x.name.value())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.value().is_some_and(|x| x.action.value() == "enable")
+    }
+}
+
+#[derive(Debug, Clone, Span, Parse, Format)]
+struct FeatureDirectiveValue {
+    name: AtomToken,
+    comma: CommaSymbol,
+    action: AtomToken,
+}
+
+/// Preprocessor directives that affect the macro definitions or the features visible from the succeeding forms.
+#[derive(Debug, Clone, Span, Parse)]
+pub(crate) enum Directive {
+    Define(Box<DefineDirective>),
+    Include(IncludeDirective),
+    Undef(UndefDirective),
+    Conditional(ConditionalDirective),
+    Feature(FeatureDirective),
+}
+
 /// `-` (`include` | `include_lib`) `(` `$PATH` `)` `.`
 ///
 /// - $PATH: [StringToken]
@@ -365,32 +596,24 @@ impl IncludeDirective {
         expanded_path
     }
 
-    pub fn resolved_path(&self, include_dirs: &[PathBuf]) -> Option<PathBuf> {
+    pub fn resolved_path(
+        &self,
+        include_dirs: &[PathBuf],
+        options: &IncludeOptions,
+    ) -> Option<PathBuf> {
+        let source = options.source().as_ref();
         let path = self.var_substituted_path();
-        if matches!(self.include, Either::B(_)) && path.components().count() > 1 {
-            let app_name = if let std::path::Component::Normal(name) = path.components().next()? {
-                name.to_str()?
-            } else {
-                return None;
-            };
-            match crate::erl::code_lib_dir(app_name) {
-                Err(e) => {
-                    log::warn!("{}", e);
-                    None
-                }
-                Ok(mut resolved_path) => {
-                    resolved_path.extend(path.components().skip(1));
-                    log::debug!("Resolved include path: {:?}", resolved_path);
-                    Some(resolved_path)
-                }
-            }
-        } else if path.exists() {
+        if source.exists(&path) {
             log::debug!("Resolved include path: {:?}", path);
             Some(path)
+        } else if matches!(self.include, Either::B(_)) && path.components().count() > 1 {
+            let resolved_path = options.resolve_lib_path(&path)?;
+            log::debug!("Resolved include path: {:?}", resolved_path);
+            Some(resolved_path)
         } else {
             for dir in include_dirs {
                 let candidate_path = dir.join(&path);
-                if candidate_path.exists() {
+                if source.exists(&candidate_path) {
                     log::debug!("Resolved include path: {:?}", candidate_path);
                     return Some(candidate_path);
                 }
@@ -398,11 +621,84 @@ impl IncludeDirective {
             None
         }
     }
+
+    /// Returns the paths looked up by [IncludeDirective::resolved_path()].
+    ///
+    /// `None` is returned for `-include_lib` of an application
+    /// as its directory is not looked up by a fixed set of paths.
+    pub(crate) fn candidate_paths(&self, include_dirs: &[PathBuf]) -> Option<Vec<PathBuf>> {
+        let path = self.var_substituted_path();
+        if matches!(self.include, Either::B(_)) && path.components().count() > 1 {
+            return None;
+        }
+        let candidates = include_dirs.iter().map(|dir| dir.join(&path));
+        Some(std::iter::once(path.clone()).chain(candidates).collect())
+    }
+}
+
+/// A region that could not be parsed as a form.
+///
+/// This item appears only when the error-tolerant mode is enabled, and the original text of the region is kept as-is.
+#[derive(Debug, Clone)]
+pub struct SkippedForm {
+    start: Position,
+    end: Position,
+}
+
+impl SkippedForm {
+    /// Skips tokens until the next top-level `.` (i.e., a dot followed by a whitespace, a comment or EOF).
+    ///
+    /// If a tokenize error occurs, the rest of the form is skipped without tokenizing it.
+    pub(crate) fn parse_until_dot(ts: &mut TokenStream) -> Self {
+        let Ok(start) = ts.next_token_start_position() else {
+            let (start, end) = ts.skip_untokenizable_form();
+            return Self { start, end };
+        };
+        let text = ts.text();
+        let result = ts.with_macro_expand_disabled(|ts| {
+            while let Some(token) = ts.next().transpose()? {
+                if !matches!(&token, LexicalToken::Symbol(x) if x.value() == Symbol::Dot) {
+                    continue;
+                }
+                let is_form_end = text
+                    .get(token.end_position().offset()..)
+                    .and_then(|s| s.chars().next())
+                    .is_none_or(|c| c.is_whitespace() || c == '%');
+                if is_form_end {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        let end = if result.is_ok() {
+            ts.prev_token_end_position()
+        } else {
+            ts.skip_untokenizable_form().1
+        };
+        Self { start, end }
+    }
+}
+
+impl Span for SkippedForm {
+    fn start_position(&self) -> Position {
+        self.start
+    }
+
+    fn end_position(&self) -> Position {
+        self.end
+    }
+}
+
+impl Format for SkippedForm {
+    fn format(&self, fmt: &mut Formatter) {
+        fmt.add_span(self);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::items::Module;
 
     #[test]
     fn define_directive_works() {
@@ -457,6 +753,97 @@ mod tests {
         }
     }
 
+    #[test]
+    fn conditional_macro_defines_works() {
+        let text = indoc::indoc! {"
+            -ifdef(TEST).
+            -define(OPEN, [).
+            -else.
+            -define(OPEN, {).
+            -endif.
+
+
+            foo() ->
+                ?OPEN 1, 2].
+            "};
+        crate::assert_format!(format!("-define(TEST, true).\n{text}"), Module);
+
+        let text = indoc::indoc! {"
+            -ifndef(TEST).
+            -define(O(X), {X).
+            -elif(false).
+            -define(O(X), (X).
+            -else.
+            -define(O(X), [X).
+            -endif.
+
+
+            foo() ->
+                ?O(1), 2].
+            "};
+        crate::assert_format!(format!("-define(TEST, true).\n{text}"), Module);
+
+        let text = indoc::indoc! {"
+            -define(OPEN, [).
+            -undef(OPEN).
+            -define(OPEN, {).
+
+
+            foo() ->
+                ?OPEN 1, 2}.
+            "};
+        crate::assert_format!(text, Module);
+
+        // `- endif` inside a function body is not a directive.
+        let text = indoc::indoc! {"
+            -define(TEST, true).
+            -define(OPEN, [).
+            -ifdef(TEST).
+
+
+            foo(X) ->
+                X - endif.
+
+
+            -else.
+            -define(OPEN, {).
+            -endif.
+
+
+            bar() ->
+                ?OPEN 1, 2].
+            "};
+        crate::assert_format!(text, Module);
+    }
+
+    #[test]
+    fn conditional_block_works() {
+        let text = indoc::indoc! {"
+            -ifdef(foo).
+            -define(A, 1).
+            -if(?A > 0).
+            -define(B, 2).
+            -elif(false).
+            -define(B, 3).
+            -else.
+            -define(B, 4).
+            -endif.
+            -endif.
+            "};
+        crate::assert_format!(text, ConditionalBlock);
+
+        let text = "-ifdef(foo).\n-define(A, 1).\n";
+        assert!(crate::Options::new()
+            .format_text::<ConditionalBlock>(text)
+            .is_err());
+
+        // Unbalanced directives are formatted as standalone forms.
+        let texts = ["-else.\n", "-endif.\n", "-ifdef(foo).\n-else.\n"];
+        for text in texts {
+            crate::assert_format!(text, Module);
+        }
+    }
+
     #[test]
     fn attr_works() {
         let texts = [
@@ -482,6 +869,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn doc_attr_works() {
+        let text = indoc::indoc! {r#"
+            -module(foo).
+            -moduledoc """
+              Module doc.
+
+                Indented  "line".
+              """.
+
+
+            -doc "Foo.".
+            -doc #{since => 1}.
+            -spec foo() -> ok.
+            foo() ->
+                ok.
+
+
+            -doc(~"""
+                Bar.
+              """).
+            bar() ->
+                ok.
+            "#};
+        crate::assert_format!(text, Module);
+    }
+
+    #[test]
+    fn feature_directive_works() {
+        let text = indoc::indoc! {"
+            -feature(maybe_expr,
+                     disable).
+
+
+            maybe(X) ->
+                {maybe, X}.
+
+
+            -ifdef(A).
+            -else.
+            -endif.
+            "};
+        crate::assert_format!(text, Module);
+
+        let text = indoc::indoc! {"
+            maybe(else) ->
+                ok.
+            "};
+        assert!(crate::Options::new().format_text::<Module>(text).is_err());
+        let formatted = crate::Options::new()
+            .otp_release(26)
+            .format_text::<Module>(text)
+            .unwrap();
+        assert_eq!(formatted, text);
+        let formatted = crate::Options::new()
+            .feature("maybe_expr", false)
+            .format_text::<Module>(text)
+            .unwrap();
+        assert_eq!(formatted, text);
+    }
+
     #[test]
     fn record_decl_works() {
         let texts = [
@@ -610,4 +1058,62 @@ mod tests {
             crate::assert_format!(text, Form);
         }
     }
+    #[test]
+    fn skipped_form_works() {
+        let text = indoc::indoc! {"
+            foo()->
+                : foo, X#rec.field.
+            bar()->bar.
+            baz()->
+              ?EFMT_DUMMY baz.
+            "};
+        let expected = indoc::indoc! {"
+            foo()->
+                : foo, X#rec.field.
+
+
+            bar() ->
+                bar.
+
+
+            baz()->
+              ?EFMT_DUMMY baz.
+            "};
+        let formatted = crate::Options::new()
+            .error_tolerant()
+            .format_text::<crate::items::Module>(text)
+            .unwrap();
+        similar_asserts::assert_str_eq!(formatted, expected);
+    }
+
+    #[test]
+    fn untokenizable_form_works() {
+        let text = indoc::indoc! {"
+            foo()->
+                `foo`.
+            bar()->bar.
+            `baz.
+            qux()->qux.
+            "};
+        let expected = indoc::indoc! {"
+            foo()->
+                `foo`.
+
+
+            bar() ->
+                bar.
+
+
+            `baz.
+
+
+            qux() ->
+                qux.
+            "};
+        let formatted = crate::Options::new()
+            .error_tolerant()
+            .format_text::<crate::items::Module>(text)
+            .unwrap();
+        similar_asserts::assert_str_eq!(formatted, expected);
+    }
 }
@@ -67,6 +67,7 @@ impl_traits!(CaseKeyword, Case);
 pub struct CatchKeyword(KeywordToken);
 impl_traits!(CatchKeyword, Catch);
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Span, Format)]
 pub struct CondKeyword(KeywordToken);
 impl_traits!(CondKeyword, Cond);
@@ -87,6 +88,7 @@ impl_traits!(FunKeyword, Fun);
 pub struct IfKeyword(KeywordToken);
 impl_traits!(IfKeyword, If);
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Span, Format)]
 pub struct LetKeyword(KeywordToken);
 impl_traits!(LetKeyword, Let);
@@ -221,12 +221,11 @@ impl Parse for MacroArg {
                     | Keyword::Receive => {
                         level += 1;
                     }
-                    Keyword::Fun => {
-                        if ts.peek::<OpenParenSymbol>().is_some()
-                            || ts.peek::<(LexicalToken, OpenParenSymbol)>().is_some()
-                        {
-                            level += 1;
-                        }
+                    Keyword::Fun
+                        if (ts.peek::<OpenParenSymbol>().is_some()
+                            || ts.peek::<(LexicalToken, OpenParenSymbol)>().is_some()) =>
+                    {
+                        level += 1;
                     }
                     Keyword::End => {
                         if level == 0 {
@@ -578,4 +577,58 @@ mod tests {
             crate::assert_format!(text, Module);
         }
     }
+
+    #[test]
+    fn define_macro_option_works() {
+        let text = indoc::indoc! {"
+            -ifdef(TEST).
+            -define(OPEN, [).
+            -endif.
+
+
+            foo() ->
+                ?OPEN 1, 2].
+            "};
+        let formatted = crate::Options::new()
+            .define_macro("TEST", None)
+            .format_text::<Module>(text)
+            .unwrap();
+        similar_asserts::assert_str_eq!(formatted, text);
+
+        let text = indoc::indoc! {"
+            foo() ->
+                ?OPEN 1, 2].
+            "};
+        let formatted = crate::Options::new()
+            .define_macro("OPEN", Some("["))
+            .format_text::<Module>(text)
+            .unwrap();
+        similar_asserts::assert_str_eq!(formatted, text);
+
+        assert!(crate::Options::new()
+            .define_macro("foo bar", None)
+            .format_text::<Module>(text)
+            .is_err());
+    }
+
+    #[test]
+    fn memory_include_source_works() {
+        let text = indoc::indoc! {"
+            -include(\"foo.hrl\").
+            -include_lib(\"bar/include/bar.hrl\").
+
+
+            foo() ->
+                ?OPEN 1, 2?CLOSE.
+            "};
+        let source = crate::parse::MemoryIncludeSource::new()
+            .file("foo.hrl", "-define(OPEN, [).")
+            .file("bar/include/bar.hrl", "-include(\"baz.hrl\").")
+            .file("bar/include/baz.hrl", "-define(CLOSE, ]).");
+        let formatted = crate::Options::new()
+            .include_source(source)
+            .format_text::<Module>(text)
+            .unwrap();
+        similar_asserts::assert_str_eq!(formatted, text);
+    }
 }
@@ -1,5 +1,7 @@
 use crate::format::{Format, Formatter};
-use crate::items::forms::DefineDirective;
+use crate::items::forms::{
+    ConditionalBlock, ConditionalDirective, DefineDirective, EndifDirective, SkippedForm,
+};
 use crate::items::{forms, Form};
 use crate::parse::{self, Parse, TokenStream};
 use crate::span::{Position, Span};
@@ -15,12 +17,49 @@ pub struct Module {
     eof: Position,
 }
 
+impl Module {
+    /// Returns the forms in this module with the conditional blocks flattened.
+    pub(crate) fn leaves(&self) -> Vec<Leaf<'_>> {
+        let mut leaves = Vec::new();
+        collect_leaves(&self.forms, &mut leaves);
+        leaves
+    }
+
+    /// Formats only the forms that start at or after `start`.
+    pub(crate) fn format_from(&self, fmt: &mut Formatter, start: Position) {
+        let leaves = self.leaves();
+        let i = leaves
+            .iter()
+            .position(|leaf| start <= leaf.start_position())
+            .unwrap_or(leaves.len());
+        format_forms(fmt, &leaves[i..]);
+    }
+}
+
 impl Parse for Module {
     fn parse(ts: &mut TokenStream) -> parse::Result<Self> {
         let sof = ts.prev_token_end_position();
         let mut forms = Vec::new();
-        while !ts.is_eof()? {
-            forms.push(ts.parse()?);
+        loop {
+            let result = match ts.is_eof() {
+                Ok(true) => break,
+                Ok(false) => ts.parse(),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(form) => forms.push(form),
+                Err(e) if ts.is_error_tolerant() => {
+                    // Reports the furthest error as it usually points at the actual problem.
+                    let e = ts
+                        .take_last_error()
+                        .filter(|e0| e.position() <= e0.position())
+                        .unwrap_or(e);
+                    let skipped = SkippedForm::parse_until_dot(ts);
+                    log::warn!("Skipped formatting an unparsable form.\n{e}");
+                    forms.push(Form::skipped(skipped));
+                }
+                Err(e) => return Err(e),
+            }
         }
         let eof = ts.next_token_start_position()?;
         Ok(Self { sof, forms, eof })
@@ -29,39 +68,130 @@ impl Parse for Module {
 
 impl Format for Module {
     fn format(&self, fmt: &mut Formatter) {
-        let mut state = FormatState {
-            is_last_spec: false,
-            pending_constants: Vec::new(),
-        };
-        let mut is_last_fun_decl = false;
-
-        for form in &self.forms {
-            if is_last_fun_decl {
-                fmt.add_newlines(THREE);
-                is_last_fun_decl = false;
-            }
+        format_forms(fmt, &self.leaves());
+    }
+}
 
-            if state.pend_if_need(fmt, form) {
-                continue;
-            }
-            state.flush_pendings(fmt);
-            if state.pend_if_need(fmt, form) {
-                continue;
-            }
+/// A non-block form or a conditional directive.
+///
+/// Conditional blocks are formatted as flat sequences of these so that
+/// the forms inside a block are laid out the same as the ones outside.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Leaf<'a> {
+    Form(&'a Form),
+    Directive(&'a ConditionalDirective),
+    Endif(&'a EndifDirective),
+}
 
-            state.insert_two_empty_newlines_if_need(fmt, form);
+impl<'a> Leaf<'a> {
+    fn form(self) -> Option<&'a Form> {
+        if let Self::Form(x) = self {
+            Some(x)
+        } else {
+            None
+        }
+    }
 
-            form.format(fmt);
-            fmt.add_newline();
-            is_last_fun_decl = form.is_func_decl();
+    fn is_func_spec(&self) -> bool {
+        self.form().is_some_and(|x| x.is_func_spec())
+    }
+
+    fn is_func_doc(&self) -> bool {
+        self.form().is_some_and(|x| x.is_func_doc())
+    }
+
+    fn is_func_decl(&self) -> bool {
+        self.form().is_some_and(|x| x.is_func_decl())
+    }
+}
+
+impl Span for Leaf<'_> {
+    fn start_position(&self) -> Position {
+        match self {
+            Self::Form(x) => x.start_position(),
+            Self::Directive(x) => x.start_position(),
+            Self::Endif(x) => x.start_position(),
+        }
+    }
+
+    fn end_position(&self) -> Position {
+        match self {
+            Self::Form(x) => x.end_position(),
+            Self::Directive(x) => x.end_position(),
+            Self::Endif(x) => x.end_position(),
+        }
+    }
+}
+
+impl Format for Leaf<'_> {
+    fn format(&self, fmt: &mut Formatter) {
+        match self {
+            Self::Form(x) => x.format(fmt),
+            Self::Directive(x) => x.format(fmt),
+            Self::Endif(x) => x.format(fmt),
+        }
+    }
+}
+
+fn collect_leaves<'a>(forms: &'a [Form], leaves: &mut Vec<Leaf<'a>>) {
+    for form in forms {
+        if let Some(forms::Form::Conditional(block)) = form.get() {
+            collect_block_leaves(block, leaves);
+        } else {
+            leaves.push(Leaf::Form(form));
+        }
+    }
+}
+
+fn collect_block_leaves<'a>(block: &'a ConditionalBlock, leaves: &mut Vec<Leaf<'a>>) {
+    for (directive, forms) in block.branches() {
+        leaves.push(Leaf::Directive(directive));
+        collect_leaves(forms, leaves);
+    }
+    leaves.push(Leaf::Endif(block.endif()));
+}
+
+pub(super) fn format_conditional_block(fmt: &mut Formatter, block: &ConditionalBlock) {
+    let mut leaves = Vec::new();
+    collect_block_leaves(block, &mut leaves);
+    format_forms(fmt, &leaves);
+}
+
+fn format_forms(fmt: &mut Formatter, forms: &[Leaf]) {
+    let mut state = FormatState {
+        is_last_spec: false,
+        is_last_doc: false,
+        pending_constants: Vec::new(),
+    };
+    let mut is_last_fun_decl = false;
+
+    for form in forms {
+        if is_last_fun_decl {
+            fmt.add_newlines(THREE);
+            is_last_fun_decl = false;
         }
 
+        if state.pend_if_need(fmt, form) {
+            continue;
+        }
         state.flush_pendings(fmt);
+        if state.pend_if_need(fmt, form) {
+            continue;
+        }
+
+        state.insert_two_empty_newlines_if_need(fmt, form);
+
+        form.format(fmt);
+        fmt.add_newline();
+        is_last_fun_decl = form.is_func_decl();
     }
+
+    state.flush_pendings(fmt);
 }
 
 struct FormatState<'a> {
     is_last_spec: bool,
+    is_last_doc: bool,
     pending_constants: Vec<&'a DefineDirective>,
 }
 
@@ -93,8 +223,8 @@ impl<'a> FormatState<'a> {
         }
     }
 
-    fn pend_if_need(&mut self, fmt: &Formatter, form: &'a Form) -> bool {
-        if let forms::Form::Define(define) = form.get() {
+    fn pend_if_need(&mut self, fmt: &Formatter, form: &Leaf<'a>) -> bool {
+        if let Some(forms::Form::Define(define)) = form.form().and_then(|x| x.get()) {
             if define.variables().is_some() {
                 return false;
             }
@@ -116,16 +246,18 @@ impl<'a> FormatState<'a> {
         }
     }
 
-    fn insert_two_empty_newlines_if_need(&mut self, fmt: &mut Formatter, form: &'a Form) {
-        if form.is_func_decl() && !self.is_last_spec {
+    fn insert_two_empty_newlines_if_need(&mut self, fmt: &mut Formatter, form: &Leaf) {
+        if form.is_func_decl() && !self.is_last_spec && !self.is_last_doc {
             fmt.flush_non_preceding_comments(form);
             fmt.add_newlines(THREE);
         }
 
-        self.is_last_spec = form.is_func_spec();
-        if form.is_func_spec() {
+        // `-doc` attributes are kept together with the following `-spec` and function.
+        if (form.is_func_spec() || form.is_func_doc()) && !self.is_last_doc {
             fmt.flush_non_preceding_comments(form);
             fmt.add_newlines(THREE);
         }
+        self.is_last_spec = form.is_func_spec();
+        self.is_last_doc = form.is_func_doc();
     }
 }
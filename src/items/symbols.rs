@@ -1,5 +1,5 @@
 use crate::format::Format;
-use crate::items::tokens::SymbolToken;
+use crate::items::tokens::{ExtSymbol, ExtSymbolToken, SymbolToken};
 use crate::parse::{self, Parse, TokenStream};
 use crate::span::{Position, Span};
 use erl_tokenize::values::Symbol;
@@ -19,6 +19,21 @@ macro_rules! impl_traits {
     };
 }
 
+macro_rules! impl_ext_traits {
+    ($name:ident, $value:ident) => {
+        impl Parse for $name {
+            fn parse(ts: &mut TokenStream) -> parse::Result<Self> {
+                let token: ExtSymbolToken = ts.parse()?;
+                if token.value() == ExtSymbol::$value {
+                    Ok(Self(token))
+                } else {
+                    Err(parse::Error::unexpected_token(ts, token.into()))
+                }
+            }
+        }
+    };
+}
+
 #[derive(Debug, Clone, Span, Format)]
 pub struct OpenSquareSymbol(SymbolToken);
 impl_traits!(OpenSquareSymbol, OpenSquare);
@@ -106,6 +121,7 @@ impl QuestionSymbol {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Span, Format)]
 pub struct DoubleQuestionSymbol(SymbolToken);
 impl_traits!(DoubleQuestionSymbol, DoubleQuestion);
@@ -150,6 +166,18 @@ impl_traits!(DoubleRightArrowSymbol, DoubleRightArrow);
 pub struct DoubleLeftArrowSymbol(SymbolToken);
 impl_traits!(DoubleLeftArrowSymbol, DoubleLeftArrow);
 
+#[derive(Debug, Clone, Span, Format)]
+pub struct StrictLeftArrowSymbol(ExtSymbolToken);
+impl_ext_traits!(StrictLeftArrowSymbol, StrictLeftArrow);
+
+#[derive(Debug, Clone, Span, Format)]
+pub struct StrictDoubleLeftArrowSymbol(ExtSymbolToken);
+impl_ext_traits!(StrictDoubleLeftArrowSymbol, StrictDoubleLeftArrow);
+
+#[derive(Debug, Clone, Span, Format)]
+pub struct DoubleAmpersandSymbol(ExtSymbolToken);
+impl_ext_traits!(DoubleAmpersandSymbol, DoubleAmpersand);
+
 #[derive(Debug, Clone, Span, Format)]
 pub struct DoubleRightAngleSymbol(SymbolToken);
 impl_traits!(DoubleRightAngleSymbol, DoubleRightAngle);
@@ -15,6 +15,7 @@ pub enum LexicalToken {
     Keyword(KeywordToken),
     String(StringToken),
     Symbol(SymbolToken),
+    ExtSymbol(ExtSymbolToken),
     Variable(VariableToken),
 }
 
@@ -28,6 +29,7 @@ impl LexicalToken {
             Self::Keyword(x) => (&mut x.start, &mut x.end),
             Self::String(x) => (&mut x.start, &mut x.end),
             Self::Symbol(x) => (&mut x.start, &mut x.end),
+            Self::ExtSymbol(x) => (&mut x.start, &mut x.end),
             Self::Variable(x) => (&mut x.start, &mut x.end),
         };
         *start = span.start_position();
@@ -56,6 +58,7 @@ pub enum VisibleToken {
     Keyword(KeywordToken),
     String(StringToken),
     Symbol(SymbolToken),
+    ExtSymbol(ExtSymbolToken),
     Variable(VariableToken),
 }
 
@@ -76,7 +79,10 @@ impl VisibleToken {
                 return true;
             }
         }
-        if !matches!((self, other), (Self::Symbol(_), _) | (_, Self::Symbol(_))) {
+        if !matches!(
+            (self, other),
+            (Self::Symbol(_) | Self::ExtSymbol(_), _) | (_, Self::Symbol(_) | Self::ExtSymbol(_))
+        ) {
             return true;
         }
         false
@@ -87,6 +93,7 @@ impl VisibleToken {
             Self::Atom(x) => Some(x.value()),
             Self::Variable(x) => Some(x.value()),
             Self::Symbol(x) => Some(x.value().as_str()),
+            Self::ExtSymbol(x) => Some(x.value().as_str()),
             Self::Keyword(x) => Some(x.value().as_str()),
             _ => None,
         }
@@ -302,6 +309,48 @@ impl SymbolToken {
 
 impl_traits!(SymbolToken, Symbol);
 
+/// Symbols that are not supported by [erl_tokenize] (i.e., ones introduced in OTP 28).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum ExtSymbol {
+    /// `<:-`
+    StrictLeftArrow,
+
+    /// `<:=`
+    StrictDoubleLeftArrow,
+
+    /// `&&`
+    DoubleAmpersand,
+}
+
+impl ExtSymbol {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::StrictLeftArrow => "<:-",
+            Self::StrictDoubleLeftArrow => "<:=",
+            Self::DoubleAmpersand => "&&",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct ExtSymbolToken {
+    value: ExtSymbol,
+    start: Position,
+    end: Position,
+}
+
+impl ExtSymbolToken {
+    pub fn new(value: ExtSymbol, start: Position, end: Position) -> Self {
+        Self { value, start, end }
+    }
+
+    pub fn value(&self) -> ExtSymbol {
+        self.value
+    }
+}
+
+impl_traits!(ExtSymbolToken, ExtSymbol);
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct VariableToken {
     value: String,
@@ -1,24 +1,39 @@
 use crate::format::{Format, Formatter};
-use crate::parse::{IncludeOptions, Parse, TokenStream};
-use std::path::Path;
+use crate::format_cache::FormatCache;
+use crate::items::Module;
+use crate::line_ending::{LineEnding, SourceText};
+use crate::parse::{IncludeMemoryCache, IncludeOptions, IncludeSource, Parse, TokenStream};
+use crate::range::{FormatRange, TextEdit};
+use std::path::{Path, PathBuf};
 
+pub mod cache;
+pub mod config;
 pub mod diff;
+pub mod encoding;
 pub mod error;
 pub mod files;
 pub mod format;
 pub mod items;
+pub mod line_ending;
+pub mod lsp;
 pub mod parse;
+pub mod range;
+pub mod report;
 pub mod span;
 
+pub use crate::error::Error;
+
 pub(crate) mod erl;
+pub(crate) mod format_cache;
+pub(crate) mod verify;
 
 /// Formats an Erlang file with the default options.
-pub fn format_file<T: Parse + Format, P: AsRef<Path>>(path: P) -> anyhow::Result<String> {
+pub fn format_file<T: Parse + Format, P: AsRef<Path>>(path: P) -> Result<String, Error> {
     Options::new().format_file::<T, P>(path)
 }
 
 /// Formats an Erlang text with the default options.
-pub fn format_text<T: Parse + Format>(text: &str) -> anyhow::Result<String> {
+pub fn format_text<T: Parse + Format>(text: &str) -> Result<String, Error> {
     Options::new().format_text::<T>(text)
 }
 
@@ -28,6 +43,14 @@ pub struct Options {
     max_columns: usize,
     include: IncludeOptions,
     default_off: bool,
+    error_tolerant: bool,
+    macro_defines: Vec<(String, String)>,
+    otp_release: Option<u32>,
+    features: Vec<(String, bool)>,
+    line_ending: LineEnding,
+    format_cache_dir: Option<PathBuf>,
+    verify_tokens: bool,
+    verify_idempotency: bool,
 }
 
 impl Default for Options {
@@ -36,6 +59,14 @@ impl Default for Options {
             max_columns: Self::DEFAULT_MAX_COLUMNS,
             include: IncludeOptions::default(),
             default_off: false,
+            error_tolerant: false,
+            macro_defines: Vec::new(),
+            otp_release: None,
+            features: Vec::new(),
+            line_ending: LineEnding::default(),
+            format_cache_dir: None,
+            verify_tokens: true,
+            verify_idempotency: false,
         }
     }
 }
@@ -66,49 +97,358 @@ impl Options {
         self
     }
 
+    /// Sets the directories containing applications (e.g., `apps/` of an umbrella project).
+    ///
+    /// `-include_lib("APP/...")` is resolved against `DIR/APP/...` before falling back to `code:lib_dir/1`.
+    pub fn include_lib_dirs<P: AsRef<Path>>(mut self, dirs: Vec<P>) -> Self {
+        self.include = self
+            .include
+            .lib_dirs(dirs.into_iter().map(|x| x.as_ref().to_path_buf()).collect());
+        self
+    }
+
+    /// Sets the root directory of Erlang/OTP used to resolve `-include_lib`.
+    ///
+    /// If omitted, it is detected from the location of `erl` command.
+    pub fn otp_root<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.include = self.include.otp_root(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Runs `erl` command to resolve `-include_lib` if the application is not found in the library directories.
+    pub fn include_lib_erl_fallback(mut self) -> Self {
+        self.include = self.include.erl_fallback();
+        self
+    }
+
     pub fn include_cache_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
         self.include = self.include.include_cache_dir(dir.as_ref().to_path_buf());
         self
     }
 
+    /// Limits the total size of the include cache entries (in bytes).
+    ///
+    /// If the limit is exceeded when saving a new entry, the oldest entries are removed.
+    pub fn include_cache_max_size(mut self, bytes: u64) -> Self {
+        self.include = self.include.include_cache_max_size(bytes);
+        self
+    }
+
+    pub fn include_memory_cache(mut self, cache: IncludeMemoryCache) -> Self {
+        self.include = self.include.include_memory_cache(cache);
+        self
+    }
+
+    /// Sets the source from which include files are read (see [IncludeSource]).
+    pub fn include_source<S: IncludeSource + 'static>(mut self, source: S) -> Self {
+        self.include = self.include.include_source(source);
+        self
+    }
+
     pub fn default_off(mut self) -> Self {
         self.default_off = true;
         self
     }
 
-    pub fn format_file<T: Parse + Format, P: AsRef<Path>>(self, path: P) -> anyhow::Result<String> {
-        let text = std::fs::read_to_string(&path)?;
-        let mut tokenizer = erl_tokenize::Tokenizer::new(text);
-        tokenizer.set_filepath(path);
-        self.format::<T>(tokenizer)
+    /// Keeps the forms that could not be parsed as-is instead of returning an error.
+    ///
+    /// The unparsable forms are reported as warnings via [log] and the other forms are formatted as usual.
+    pub fn error_tolerant(mut self) -> Self {
+        self.error_tolerant = true;
+        self
+    }
+
+    /// Defines a macro that is visible from the beginning of the target text (like `erlc -D$NAME=$VALUE`).
+    ///
+    /// If `value` is `None`, the macro is defined as `true`.
+    pub fn define_macro(mut self, name: &str, value: Option<&str>) -> Self {
+        self.macro_defines
+            .push((name.to_owned(), value.unwrap_or("true").to_owned()));
+        self
+    }
+
+    /// Sets the OTP release that the target text is compiled with.
+    ///
+    /// This determines the features enabled by default (e.g., `maybe_expr` is enabled since OTP 27).
+    /// If omitted, all the features known by efmt are enabled by default.
+    pub fn otp_release(mut self, release: u32) -> Self {
+        self.otp_release = Some(release);
+        self
+    }
+
+    /// Enables or disables a feature (like `erlc -enable-feature` or `-disable-feature`).
+    ///
+    /// `-feature(NAME, enable|disable).` attributes in the target text take precedence over this setting.
+    pub fn feature(mut self, name: &str, enabled: bool) -> Self {
+        self.features.push((name.to_owned(), enabled));
+        self
+    }
+
+    /// Sets the line ending of the formatted text ([LineEnding::Auto] by default).
+    ///
+    /// Note that a leading BOM in the original text is always preserved.
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
     }
 
-    pub fn format_text<T: Parse + Format>(self, text: &str) -> anyhow::Result<String> {
-        let tokenizer = erl_tokenize::Tokenizer::new(text.to_owned());
-        self.format::<T>(tokenizer)
+    /// Records the files formatted correctly by [Options::format_file()] under the given directory.
+    ///
+    /// If a file and the files included by it are not changed since the last time,
+    /// the file is regarded as formatted correctly without parsing it.
+    pub fn format_cache_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.format_cache_dir = Some(dir.as_ref().to_path_buf());
+        self
     }
 
-    fn format<T: Parse + Format>(
+    /// Checks that the formatted text consists of the same tokens as the original one (enabled by default).
+    ///
+    /// If the check fails, [Error::TokenMismatch] is returned.
+    pub fn verify_tokens(mut self, enabled: bool) -> Self {
+        self.verify_tokens = enabled;
+        self
+    }
+
+    /// Checks that formatting the formatted text again yields the same text (disabled by default).
+    ///
+    /// If the check fails, [Error::NotIdempotent] is returned.
+    /// Note that this check is not applied to range formatting.
+    pub fn verify_idempotency(mut self, enabled: bool) -> Self {
+        self.verify_idempotency = enabled;
+        self
+    }
+
+    pub fn format_file<T: Parse + Format, P: AsRef<Path>>(self, path: P) -> Result<String, Error> {
+        let text = read_file(path.as_ref())?;
+        self.format_file_text::<T, _>(path, &text)
+    }
+
+    /// Formats `text` as the content of the file at `path` (e.g., the text that the caller has already read from the file).
+    pub fn format_file_text<T: Parse + Format, P: AsRef<Path>>(
         self,
-        tokenizer: erl_tokenize::Tokenizer<String>,
-    ) -> anyhow::Result<String> {
-        let mut ts = TokenStream::new(tokenizer, self.include);
+        path: P,
+        text: &str,
+    ) -> Result<String, Error> {
+        let cache = self.format_cache_dir.as_ref().map(|dir| {
+            let fingerprint = format!("{}\n{}", std::any::type_name::<T>(), self.fingerprint());
+            FormatCache::new(
+                dir,
+                path.as_ref(),
+                text,
+                &fingerprint,
+                self.include.source().clone(),
+            )
+        });
+        if cache.as_ref().is_some_and(|cache| cache.is_formatted()) {
+            log::debug!("{:?} is formatted correctly (cached).", path.as_ref());
+            return Ok(text.to_owned());
+        }
+
+        let source = SourceText::new(text, self.line_ending);
+        let mut tokenizer = erl_tokenize::Tokenizer::new(source.text().to_owned());
+        tokenizer.set_filepath(&path);
+        let mut ts = self.token_stream(tokenizer)?;
         let item: T = ts.parse()?;
+        let dependencies = ts.include_dependencies().clone();
+        let formatted_text = self.format_item(ts, item);
+        self.verify::<T>(Some(path.as_ref()), source.text(), &formatted_text)?;
+        let formatted_text = source.restore(&formatted_text);
+        if let Some(cache) = cache.filter(|_| formatted_text == text) {
+            cache.save(path.as_ref(), &dependencies);
+        }
+        Ok(formatted_text)
+    }
+
+    pub fn format_text<T: Parse + Format>(self, text: &str) -> Result<String, Error> {
+        self.format_text_with_path::<T>(text, None)
+    }
+
+    pub(crate) fn format_text_with_path<T: Parse + Format>(
+        self,
+        text: &str,
+        path: Option<&Path>,
+    ) -> Result<String, Error> {
+        let source = SourceText::new(text, self.line_ending);
+        let mut tokenizer = erl_tokenize::Tokenizer::new(source.text().to_owned());
+        if let Some(path) = path {
+            tokenizer.set_filepath(path);
+        }
+        let mut ts = self.token_stream(tokenizer)?;
+        let item: T = ts.parse()?;
+        let formatted_text = self.format_item(ts, item);
+        self.verify::<T>(path, source.text(), &formatted_text)?;
+        Ok(source.restore(&formatted_text))
+    }
+
+    /// Formats only the top-level forms of an Erlang module file that intersect with `ranges`.
+    ///
+    /// The text outside of the returned edits is kept as-is.
+    pub fn format_file_ranges<P: AsRef<Path>>(
+        self,
+        path: P,
+        ranges: &[FormatRange],
+    ) -> Result<Vec<TextEdit>, Error> {
+        let text = read_file(path.as_ref())?;
+        self.format_file_text_ranges(path, &text, ranges)
+    }
+
+    /// Like [Options::format_file_ranges], but formats `text` as the content of the file at `path`.
+    pub fn format_file_text_ranges<P: AsRef<Path>>(
+        self,
+        path: P,
+        text: &str,
+        ranges: &[FormatRange],
+    ) -> Result<Vec<TextEdit>, Error> {
+        self.format_ranges(text, Some(path.as_ref()), ranges)
+    }
+
+    /// Formats only the top-level forms of an Erlang module text that intersect with `ranges`.
+    ///
+    /// The text outside of the returned edits is kept as-is.
+    pub fn format_text_ranges(
+        self,
+        text: &str,
+        ranges: &[FormatRange],
+    ) -> Result<Vec<TextEdit>, Error> {
+        self.format_ranges(text, None, ranges)
+    }
+
+    fn format_ranges(
+        self,
+        text: &str,
+        path: Option<&Path>,
+        ranges: &[FormatRange],
+    ) -> Result<Vec<TextEdit>, Error> {
+        let tokenizer = |text: &str| {
+            let mut tokenizer = erl_tokenize::Tokenizer::new(text.to_owned());
+            if let Some(path) = path {
+                tokenizer.set_filepath(path);
+            }
+            tokenizer
+        };
+
+        // The BOM is masked instead of stripped so that the offsets of the edits are kept.
+        let line_ending = self.line_ending.resolve(text);
+        let bom_len = line_ending::bom_len(text);
+        let text = &*line_ending::mask_bom(text);
+
+        let mut ts = self.token_stream(tokenizer(text))?;
+        let module: Module = ts.parse()?;
+
+        let mut edits = Vec::new();
+        for region in range::resolve_regions(text, &module.leaves(), ranges) {
+            // The edits must not replace the BOM.
+            let region = region.start.max(bom_len)..region.end;
+
+            // The text following the region is dropped so that the trailing comments are not emitted.
+            let mut ts = self.token_stream(tokenizer(&text[..region.end]))?;
+            let module: Module = ts.parse()?;
+
+            let start = range::position_at(text, region.start);
+            let mut formatter = Formatter::new(ts);
+            formatter.set_next_position(start);
+            if self.default_off {
+                formatter.skip_formatting();
+            }
+            module.format_from(&mut formatter, start);
+            let formatted = formatter.format(self.max_columns);
+
+            let formatted = line_ending::to_lf(formatted.trim_start_matches('\n'));
+            let new_text = line_ending.apply(&formatted);
+            if new_text != text[region.clone()] {
+                let end = range::position_at(text, region.end);
+                edits.push(TextEdit::new(start, end, new_text.into_owned()));
+            }
+        }
+        if self.verify_tokens {
+            verify::verify_tokens(path, text, &range::apply_edits(text, &edits))?;
+        }
+        Ok(edits)
+    }
+
+    fn verify<T: Parse + Format>(
+        &self,
+        path: Option<&Path>,
+        original: &str,
+        formatted: &str,
+    ) -> Result<(), Error> {
+        if self.verify_tokens {
+            verify::verify_tokens(path, original, formatted)?;
+        }
+        if self.verify_idempotency {
+            let mut options = self.clone();
+            options.verify_idempotency = false;
+            options.format_cache_dir = None;
+            let reformatted = options.format_text_with_path::<T>(formatted, path)?;
+            verify::verify_idempotency(path, formatted, &reformatted)?;
+        }
+        Ok(())
+    }
+
+    fn format_item<T: Format>(&self, ts: TokenStream, item: T) -> String {
         let mut formatter = Formatter::new(ts);
         if self.default_off {
             formatter.skip_formatting();
         }
         item.format(&mut formatter);
-        let formatted_text = formatter.format(self.max_columns);
-        Ok(formatted_text)
+        formatter.format(self.max_columns)
+    }
+
+    /// Returns a string representing the options that affect the formatting results.
+    pub(crate) fn fingerprint(&self) -> String {
+        format!(
+            "{:?}",
+            (
+                self.max_columns,
+                self.default_off,
+                self.error_tolerant,
+                &self.macro_defines,
+                self.otp_release,
+                &self.features,
+                self.line_ending,
+                self.include.fingerprint()
+            )
+        )
     }
+
+    fn token_stream(
+        &self,
+        tokenizer: erl_tokenize::Tokenizer<String>,
+    ) -> Result<TokenStream, Error> {
+        let mut ts = TokenStream::new(tokenizer, self.include.clone());
+        ts.set_error_tolerant(self.error_tolerant);
+        if let Some(release) = self.otp_release {
+            ts.set_otp_release(release);
+        }
+        for (name, enabled) in &self.features {
+            ts.set_feature(name, *enabled);
+        }
+        for (name, value) in &self.macro_defines {
+            ts.define_macro(name, value)
+                .map_err(|source| Error::InvalidMacroDefinition {
+                    name: name.clone(),
+                    value: value.clone(),
+                    source,
+                })?;
+        }
+        Ok(ts)
+    }
+}
+
+fn read_file(path: &Path) -> Result<String, Error> {
+    encoding::read_file(path)
+        .map(|(text, _)| text)
+        .map_err(|source| Error::Io {
+            path: path.to_path_buf(),
+            source,
+        })
 }
 
 #[cfg(test)]
 #[macro_export]
 macro_rules! assert_format {
     ($text:expr, $item_type:ident) => {{
-        let formatted = crate::Options::new()
+        let formatted = $crate::Options::new()
             .max_columns(20)
             .format_text::<$item_type>(&$text)
             .unwrap();
@@ -117,7 +457,7 @@ macro_rules! assert_format {
     }};
 
     ($text:expr, $expected:expr, $item_type:ident) => {{
-        let formatted = crate::Options::new()
+        let formatted = $crate::Options::new()
             .max_columns(20)
             .format_text::<$item_type>(&$text)
             .unwrap();
@@ -0,0 +1,243 @@
+//! Line endings and byte order marks.
+//!
+//! The formatter itself only deals with LF line endings.
+//! CRLF line endings and a leading UTF-8 BOM in a source text are removed before formatting
+//! and restored in the formatted text.
+//! The line breaks inside literals (e.g., multi-line strings) are part of their values, so they are kept as-is.
+use crate::parse::ext_token::{step_position, ExtToken};
+use erl_tokenize::PositionRange as _;
+use std::borrow::Cow;
+use std::ops::Range;
+
+const BOM: char = '\u{feff}';
+
+/// Line ending style of a formatted text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// Uses the same line ending as the first line of the original text (LF if the text has no line breaks).
+    #[default]
+    Auto,
+
+    /// `\n`.
+    Lf,
+
+    /// `\r\n`.
+    Crlf,
+}
+
+impl LineEnding {
+    /// Resolves [LineEnding::Auto] to the line ending used in `text`.
+    pub fn resolve(self, text: &str) -> Self {
+        match self {
+            Self::Auto => match text.find('\n') {
+                Some(i) if text[..i].ends_with('\r') => Self::Crlf,
+                _ => Self::Lf,
+            },
+            _ => self,
+        }
+    }
+
+    /// Converts the LF line endings in `text` into this style (except the ones inside literals).
+    ///
+    /// [LineEnding::Auto] is regarded as [LineEnding::Lf].
+    pub fn apply<'a>(self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Crlf => replace_outside_literals(text, "\n", "\r\n"),
+            Self::Auto | Self::Lf => Cow::Borrowed(text),
+        }
+    }
+}
+
+impl std::str::FromStr for LineEnding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "lf" => Ok(Self::Lf),
+            "crlf" => Ok(Self::Crlf),
+            _ => {
+                anyhow::bail!("unknown line ending {s:?} (expected one of `lf`, `crlf` or `auto`)")
+            }
+        }
+    }
+}
+
+/// A source text whose BOM is stripped and line endings are normalized to LF.
+#[derive(Debug)]
+pub(crate) struct SourceText {
+    text: String,
+    bom: bool,
+    line_ending: LineEnding,
+}
+
+impl SourceText {
+    pub fn new(text: &str, line_ending: LineEnding) -> Self {
+        let (bom, text) = match text.strip_prefix(BOM) {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        Self {
+            text: to_lf(text).into_owned(),
+            bom,
+            line_ending: line_ending.resolve(text),
+        }
+    }
+
+    /// Returns the normalized text.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Converts a formatted text of [SourceText::text()] into the original style.
+    pub fn restore(&self, formatted: &str) -> String {
+        let formatted = self.line_ending.apply(formatted);
+        if self.bom {
+            format!("{BOM}{formatted}")
+        } else {
+            formatted.into_owned()
+        }
+    }
+}
+
+/// Converts the CRLF line endings in `text` into LF (except the ones inside literals).
+pub(crate) fn to_lf(text: &str) -> Cow<'_, str> {
+    if !text.contains('\r') {
+        return Cow::Borrowed(text);
+    }
+    replace_outside_literals(text, "\r\n", "\n")
+}
+
+fn replace_outside_literals<'a>(text: &'a str, from: &str, to: &str) -> Cow<'a, str> {
+    let literals = multiline_literal_ranges(text);
+    let mut literals = literals.iter().peekable();
+    let mut replaced = String::with_capacity(text.len());
+    let mut last = 0;
+    for (i, _) in text.match_indices(from) {
+        while literals.next_if(|r| r.end <= i).is_some() {}
+        if literals.peek().is_some_and(|r| r.contains(&i)) {
+            continue;
+        }
+        replaced.push_str(&text[last..i]);
+        replaced.push_str(to);
+        last = i + from.len();
+    }
+    if last == 0 {
+        return Cow::Borrowed(text);
+    }
+    replaced.push_str(&text[last..]);
+    Cow::Owned(replaced)
+}
+
+/// Returns the byte ranges of the literals containing line breaks or carriage returns in `text`.
+///
+/// If `text` contains a tokenize error, the literals after the error are not included.
+fn multiline_literal_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut tokenizer = erl_tokenize::Tokenizer::new(text.to_owned());
+    loop {
+        let start = tokenizer.next_position();
+        if let Some(token) = ExtToken::scan(&text[start.offset()..]) {
+            let end = start.offset() + token.len();
+            if matches!(token, ExtToken::String { .. })
+                && text[start.offset()..end].contains(['\r', '\n'])
+            {
+                ranges.push(start.offset()..end);
+            }
+            tokenizer.set_position(step_position(start.clone(), &text[start.offset()..end]));
+            continue;
+        }
+        match tokenizer.next() {
+            Some(Ok(erl_tokenize::Token::Whitespace(_) | erl_tokenize::Token::Comment(_))) => {}
+            Some(Ok(token)) => {
+                if token.text().contains(['\r', '\n']) {
+                    ranges.push(token.start_position().offset()..token.end_position().offset());
+                }
+            }
+            Some(Err(_)) | None => break,
+        }
+    }
+    ranges
+}
+
+/// Returns the byte length of a leading BOM in `text` (`0` if there is no BOM).
+pub(crate) fn bom_len(text: &str) -> usize {
+    if text.starts_with(BOM) {
+        BOM.len_utf8()
+    } else {
+        0
+    }
+}
+
+/// Replaces a leading BOM in `text` with spaces of the same byte length so that the offsets are kept.
+pub(crate) fn mask_bom(text: &str) -> Cow<'_, str> {
+    match text.strip_prefix(BOM) {
+        Some(rest) => Cow::Owned(format!("{}{rest}", " ".repeat(bom_len(text)))),
+        None => Cow::Borrowed(text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_works() {
+        assert_eq!(LineEnding::Auto.resolve("foo.\r\nbar.\n"), LineEnding::Crlf);
+        assert_eq!(LineEnding::Auto.resolve("foo.\nbar.\r\n"), LineEnding::Lf);
+        assert_eq!(LineEnding::Auto.resolve("foo."), LineEnding::Lf);
+        assert_eq!(LineEnding::Lf.resolve("foo.\r\n"), LineEnding::Lf);
+    }
+
+    #[test]
+    fn source_text_works() {
+        let source = SourceText::new("\u{feff}foo.\r\nbar.\r\n", LineEnding::Auto);
+        assert_eq!(source.text(), "foo.\nbar.\n");
+        assert_eq!(
+            source.restore("foo.\n\nbar.\n"),
+            "\u{feff}foo.\r\n\r\nbar.\r\n"
+        );
+
+        let source = SourceText::new("foo.\r\n", LineEnding::Lf);
+        assert_eq!(source.restore(source.text()), "foo.\n");
+    }
+
+    #[test]
+    fn literal_line_breaks_are_kept() {
+        let source = SourceText::new(
+            "foo() ->\n    \"a\r\nb\",\r\n    'c\r\n'.\r\n",
+            LineEnding::Auto,
+        );
+        assert_eq!(source.text(), "foo() ->\n    \"a\r\nb\",\n    'c\r\n'.\n");
+        assert_eq!(
+            LineEnding::Crlf.apply("foo() ->\n    \"a\nb\".\n"),
+            "foo() ->\r\n    \"a\nb\".\r\n"
+        );
+
+        // `--newline lf` and `auto` (the first line ends with LF).
+        let text = "-module(foo).\r\n\r\n\r\nfoo() ->\r\n    \"a\r\nb\".\r\n";
+        for (text, line_ending) in [
+            (text, LineEnding::Lf),
+            (&text.replacen("\r\n", "\n", 1), LineEnding::Auto),
+        ] {
+            let formatted = crate::Options::new()
+                .line_ending(line_ending)
+                .format_text::<crate::items::Module>(text)
+                .unwrap();
+            assert_eq!(formatted, "-module(foo).\n\n\nfoo() ->\n    \"a\r\nb\".\n");
+        }
+    }
+
+    #[test]
+    fn format_crlf_text_works() {
+        let text = "\u{feff}%% foo\r\n-module(foo).\r\nfoo()->\r\n  ok. % bar\r\n";
+        let formatted = crate::Options::new()
+            .format_text::<crate::items::Module>(text)
+            .unwrap();
+        assert_eq!(
+            formatted,
+            "\u{feff}%% foo\r\n-module(foo).\r\n\r\n\r\nfoo() ->\r\n    ok.  % bar\r\n"
+        );
+    }
+}
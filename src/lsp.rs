@@ -0,0 +1,612 @@
+//! A minimal Language Server Protocol server providing document formatting features.
+//!
+//! The server speaks JSON-RPC over the given reader and writer (typically, stdin and stdout)
+//! and supports the following requests:
+//!
+//! - `textDocument/formatting`
+//! - `textDocument/rangeFormatting`
+//! - `textDocument/onTypeFormatting` (triggered by `.`)
+//!
+//! Include files opened in the client are read from their unsaved contents instead of the files on disk.
+use crate::items::ModuleOrConfig;
+use crate::parse::{FsIncludeSource, MemoryIncludeSource};
+use crate::range::{FormatRange, TextEdit};
+use crate::span::{Position, Span as _};
+use crate::Options;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+/// Language server.
+///
+/// `F` is a function that returns the formatting options for a document path.
+/// Note that `None` is passed to the function if the document is not a local file.
+#[derive(Debug)]
+pub struct LanguageServer<F> {
+    options: F,
+    documents: HashMap<String, String>,
+    is_shutdown: bool,
+}
+
+impl<F> LanguageServer<F>
+where
+    F: FnMut(Option<&Path>) -> Options,
+{
+    pub fn new(options: F) -> Self {
+        Self {
+            options,
+            documents: HashMap::new(),
+            is_shutdown: false,
+        }
+    }
+
+    /// Runs the server until an `exit` notification is received or the reader reaches EOF.
+    pub fn run<R: BufRead, W: Write>(mut self, mut reader: R, mut writer: W) -> anyhow::Result<()> {
+        while let Some(message) = read_message(&mut reader)? {
+            let message = match message {
+                Ok(message) => message,
+                Err((code, message)) => {
+                    log::warn!("Received an invalid message: {message}");
+                    let response = json!({
+                        "jsonrpc": "2.0",
+                        "id": Value::Null,
+                        "error": {"code": code, "message": message}
+                    });
+                    write_message(&mut writer, &response)?;
+                    continue;
+                }
+            };
+            let method = message.get("method").and_then(|m| m.as_str());
+            let params = message.get("params").cloned().unwrap_or(Value::Null);
+            match (method, message.get("id")) {
+                (Some("exit"), _) => break,
+                (Some(method), Some(id)) => {
+                    log::debug!("Received a request: method={method}, id={id}");
+                    let response = match self.handle_request(method, params) {
+                        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        Err((code, message)) => json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": {"code": code, "message": message}
+                        }),
+                    };
+                    write_message(&mut writer, &response)?;
+                }
+                (Some(method), None) => {
+                    log::debug!("Received a notification: method={method}");
+                    if let Err((_, e)) = self.handle_notification(method, params) {
+                        log::warn!("Failed to handle a {method:?} notification: {e}");
+                    }
+                }
+                (None, _) => {
+                    log::debug!("Ignored a response message: {message}");
+                }
+            }
+        }
+        if !self.is_shutdown {
+            log::warn!("The language server exited without receiving a `shutdown` request.");
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, method: &str, params: Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1, // Full
+                    "documentFormattingProvider": true,
+                    "documentRangeFormattingProvider": true,
+                    "documentOnTypeFormattingProvider": {"firstTriggerCharacter": "."}
+                },
+                "serverInfo": {"name": "efmt", "version": env!("CARGO_PKG_VERSION")}
+            })),
+            "shutdown" => {
+                self.is_shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/formatting" => {
+                let params: DocumentFormattingParams = parse_params(params)?;
+                let (text, path) = self.document(&params.text_document.uri)?;
+                let options =
+                    (self.options)(path.as_deref()).include_source(self.unsaved_include_source());
+                let formatted = options
+                    .format_text_with_path::<ModuleOrConfig>(&text, path.as_deref())
+                    .map_err(|e| (REQUEST_FAILED, e.to_string()))?;
+                if formatted == text {
+                    return Ok(json!([]));
+                }
+                let end = crate::range::position_at(&text, text.len());
+                let edit = TextEdit::new(Position::new(0, 1, 1), end, formatted);
+                Ok(json!([LspTextEdit::new(&text, &edit)]))
+            }
+            "textDocument/rangeFormatting" => {
+                let params: DocumentRangeFormattingParams = parse_params(params)?;
+                let (text, path) = self.document(&params.text_document.uri)?;
+                let range = FormatRange::Positions {
+                    start: params.range.start.to_position(&text),
+                    end: params.range.end.to_position(&text),
+                };
+                self.format_ranges(&text, path.as_deref(), range)
+                    .map_err(|e| (REQUEST_FAILED, e.to_string()))
+            }
+            "textDocument/onTypeFormatting" => {
+                let params: DocumentOnTypeFormattingParams = parse_params(params)?;
+                if params.ch != "." {
+                    return Ok(Value::Null);
+                }
+                let (text, path) = self.document(&params.text_document.uri)?;
+                let position = params.position.to_position(&text);
+                let range = FormatRange::Positions {
+                    start: position,
+                    end: position,
+                };
+
+                // The document is likely to be incomplete while typing, so errors are not reported.
+                Ok(self
+                    .format_ranges(&text, path.as_deref(), range)
+                    .unwrap_or_else(|e| {
+                        log::debug!("Failed to format on type: {e}");
+                        Value::Null
+                    }))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unsupported method: {method:?}"))),
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: Value) -> Result<(), (i64, String)> {
+        match method {
+            "textDocument/didOpen" => {
+                let params: DidOpenTextDocumentParams = parse_params(params)?;
+                self.documents
+                    .insert(params.text_document.uri, params.text_document.text);
+            }
+            "textDocument/didChange" => {
+                let params: DidChangeTextDocumentParams = parse_params(params)?;
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    self.documents.insert(params.text_document.uri, change.text);
+                }
+            }
+            "textDocument/didClose" => {
+                let params: DidCloseTextDocumentParams = parse_params(params)?;
+                self.documents.remove(&params.text_document.uri);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn document(&self, uri: &str) -> Result<(String, Option<PathBuf>), (i64, String)> {
+        let path = uri_to_path(uri);
+        if let Some(text) = self.documents.get(uri) {
+            return Ok((text.clone(), path));
+        }
+        let text = path
+            .as_ref()
+            .ok_or_else(|| (INVALID_PARAMS, format!("unknown document: {uri:?}")))
+            .and_then(|p| {
+                crate::encoding::read_file(p)
+                    .map(|(text, _)| text)
+                    .map_err(|e| (REQUEST_FAILED, e.to_string()))
+            })?;
+        Ok((text, path))
+    }
+
+    fn format_ranges(
+        &mut self,
+        text: &str,
+        path: Option<&Path>,
+        range: FormatRange,
+    ) -> anyhow::Result<Value> {
+        let options = (self.options)(path).include_source(self.unsaved_include_source());
+        let edits = options.format_ranges(text, path, &[range])?;
+        Ok(json!(edits
+            .iter()
+            .map(|edit| LspTextEdit::new(text, edit))
+            .collect::<Vec<_>>()))
+    }
+
+    /// Makes an include source that prefers the (possibly unsaved) contents of the open documents to the files on disk.
+    fn unsaved_include_source(&self) -> MemoryIncludeSource {
+        let mut source = MemoryIncludeSource::new().fallback(FsIncludeSource);
+        for (uri, text) in &self.documents {
+            if let Some(path) = uri_to_path(uri) {
+                source.insert(path, text);
+            }
+        }
+        source
+    }
+}
+
+fn parse_params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, (i64, String)> {
+    serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+}
+
+/// Reads a message from `reader`.
+///
+/// The outer error (I/O error) is fatal, while the inner one (malformed message) is to be reported to the client.
+fn read_message<R: BufRead>(
+    reader: &mut R,
+) -> std::io::Result<Option<Result<Value, (i64, String)>>> {
+    let mut content_length = None;
+    let mut header_error = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                match value.trim().parse::<usize>() {
+                    Ok(n) => content_length = Some(n),
+                    Err(e) => header_error = Some(format!("invalid `Content-Length` header: {e}")),
+                }
+            }
+        }
+    }
+
+    if let Some(e) = header_error {
+        return Ok(Some(Err((INVALID_REQUEST, e))));
+    }
+    let Some(content_length) = content_length else {
+        return Ok(Some(Err((
+            INVALID_REQUEST,
+            "missing `Content-Length` header".to_owned(),
+        ))));
+    };
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content)?;
+    let message = match serde_json::from_slice::<Value>(&content) {
+        Ok(message) if message.is_object() => Ok(message),
+        Ok(message) => Err((
+            INVALID_REQUEST,
+            format!("expected a JSON object but got {message}"),
+        )),
+        Err(e) => Err((PARSE_ERROR, e.to_string())),
+    };
+    Ok(Some(message))
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> anyhow::Result<()> {
+    let content = serde_json::to_string(message)?;
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDocumentIdentifier {
+    uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDocumentItem {
+    uri: String,
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDocumentContentChangeEvent {
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidOpenTextDocumentParams {
+    text_document: TextDocumentItem,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidChangeTextDocumentParams {
+    text_document: TextDocumentIdentifier,
+    content_changes: Vec<TextDocumentContentChangeEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DidCloseTextDocumentParams {
+    text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentFormattingParams {
+    text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentRangeFormattingParams {
+    text_document: TextDocumentIdentifier,
+    range: LspRange,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DocumentOnTypeFormattingParams {
+    text_document: TextDocumentIdentifier,
+    position: LspPosition,
+    ch: String,
+}
+
+/// A position in a document (zero-based line and UTF-16 based character offset).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct LspPosition {
+    line: usize,
+    character: usize,
+}
+
+impl LspPosition {
+    fn from_position(text: &str, position: Position) -> Self {
+        let offset = position.offset();
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            line: text[..offset].matches('\n').count(),
+            character: text[line_start..offset].encode_utf16().count(),
+        }
+    }
+
+    fn to_position(self, text: &str) -> Position {
+        let line_start = if self.line == 0 {
+            0
+        } else {
+            text.match_indices('\n')
+                .nth(self.line - 1)
+                .map_or(text.len(), |(i, _)| i + 1)
+        };
+        let line_end = text[line_start..]
+            .find('\n')
+            .map_or(text.len(), |i| line_start + i);
+
+        let mut offset = line_start;
+        let mut character = 0;
+        for c in text[line_start..line_end].chars() {
+            if character >= self.character {
+                break;
+            }
+            character += c.len_utf16();
+            offset += c.len_utf8();
+        }
+        crate::range::position_at(text, offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct LspRange {
+    start: LspPosition,
+    end: LspPosition,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LspTextEdit {
+    range: LspRange,
+    new_text: String,
+}
+
+impl LspTextEdit {
+    fn new(text: &str, edit: &TextEdit) -> Self {
+        Self {
+            range: LspRange {
+                start: LspPosition::from_position(text, edit.start_position()),
+                end: LspPosition::from_position(text, edit.end_position()),
+            },
+            new_text: edit.new_text().to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(value: Value) -> String {
+        let content = value.to_string();
+        format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+    }
+
+    fn run(messages: &[Value]) -> Vec<Value> {
+        let input = messages
+            .iter()
+            .map(|m| message(m.clone()))
+            .collect::<String>();
+        let mut output = Vec::new();
+        LanguageServer::new(|_: Option<&Path>| Options::new().max_columns(20).disable_include())
+            .run(input.as_bytes(), &mut output)
+            .unwrap();
+
+        let mut reader = output.as_slice();
+        let mut responses = Vec::new();
+        while let Some(response) = read_message(&mut reader).unwrap() {
+            responses.push(response.unwrap());
+        }
+        responses
+    }
+
+    #[test]
+    fn formatting_works() {
+        let uri = "file:///tmp/foo.erl";
+        let responses = run(&[
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": uri, "languageId": "erlang", "version": 1,
+                                 "text": "foo()->foo.\nbar()->bar.\n"}
+            }}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/formatting", "params": {
+                "textDocument": {"uri": uri}, "options": {"tabSize": 4, "insertSpaces": true}
+            }}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "textDocument/rangeFormatting", "params": {
+                "textDocument": {"uri": uri},
+                "range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 3}},
+                "options": {"tabSize": 4, "insertSpaces": true}
+            }}),
+            json!({"jsonrpc": "2.0", "id": 4, "method": "textDocument/onTypeFormatting", "params": {
+                "textDocument": {"uri": uri},
+                "position": {"line": 0, "character": 11},
+                "ch": ".",
+                "options": {"tabSize": 4, "insertSpaces": true}
+            }}),
+            json!({"jsonrpc": "2.0", "id": 5, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ]);
+        assert_eq!(responses.len(), 5);
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["documentFormattingProvider"],
+            json!(true)
+        );
+        assert_eq!(
+            responses[1]["result"],
+            json!([{
+                "range": {"start": {"line": 0, "character": 0}, "end": {"line": 2, "character": 0}},
+                "newText": "foo() ->\n    foo.\n\n\nbar() ->\n    bar.\n"
+            }])
+        );
+        assert_eq!(
+            responses[2]["result"],
+            json!([{
+                "range": {"start": {"line": 1, "character": 0}, "end": {"line": 2, "character": 0}},
+                "newText": "bar() ->\n    bar.\n"
+            }])
+        );
+        assert_eq!(
+            responses[3]["result"],
+            json!([{
+                "range": {"start": {"line": 0, "character": 0}, "end": {"line": 1, "character": 0}},
+                "newText": "foo() ->\n    foo.\n"
+            }])
+        );
+        assert_eq!(responses[4]["result"], Value::Null);
+    }
+
+    #[test]
+    fn unsaved_include_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let header = dir.path().join("foo.hrl");
+        std::fs::write(&header, "-define(OPEN, {).\n")?;
+        let header_uri = format!("file://{}", header.display());
+        let uri = format!("file://{}", dir.path().join("foo.erl").display());
+
+        let input = [
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": header_uri, "languageId": "erlang", "version": 1,
+                                 "text": "-define(OPEN, [).\n"}
+            }}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": uri, "languageId": "erlang", "version": 1,
+                                 "text": "-include(\"foo.hrl\").\nfoo()->?OPEN 1,2].\n"}
+            }}),
+            json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/formatting", "params": {
+                "textDocument": {"uri": uri}, "options": {"tabSize": 4, "insertSpaces": true}
+            }}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "textDocument/rangeFormatting", "params": {
+                "textDocument": {"uri": uri},
+                "range": {"start": {"line": 1, "character": 0}, "end": {"line": 1, "character": 3}},
+                "options": {"tabSize": 4, "insertSpaces": true}
+            }}),
+        ]
+        .map(message)
+        .concat();
+        let mut output = Vec::new();
+        LanguageServer::new(|_: Option<&Path>| Options::new().max_columns(20))
+            .run(input.as_bytes(), &mut output)?;
+
+        // The unsaved content of the open header is used instead of the one on disk.
+        let mut reader = output.as_slice();
+        let mut responses = Vec::new();
+        while let Some(response) = read_message(&mut reader)? {
+            responses.push(response.expect("malformed response"));
+        }
+        assert_eq!(responses.len(), 2);
+        assert_eq!(
+            responses[0]["result"],
+            json!([{
+                "range": {"start": {"line": 0, "character": 0}, "end": {"line": 2, "character": 0}},
+                "newText": "-include(\"foo.hrl\").\n\n\nfoo() ->\n    ?OPEN 1, 2].\n"
+            }])
+        );
+        assert_eq!(
+            responses[1]["result"],
+            json!([{
+                "range": {"start": {"line": 1, "character": 0}, "end": {"line": 2, "character": 0}},
+                "newText": "foo() ->\n    ?OPEN 1, 2].\n"
+            }])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_message_works() {
+        let input = [
+            "Content-Length: 5\r\n\r\n{bad}".to_owned(),
+            "Content-Length: 3\r\n\r\n[1]".to_owned(),
+            "Content-Length: x\r\n\r\n".to_owned(),
+            message(json!({"jsonrpc": "2.0", "id": 1, "method": "shutdown"})),
+            message(json!({"jsonrpc": "2.0", "method": "exit"})),
+        ]
+        .concat();
+        let mut output = Vec::new();
+        LanguageServer::new(|_: Option<&Path>| Options::new())
+            .run(input.as_bytes(), &mut output)
+            .unwrap();
+
+        let mut reader = output.as_slice();
+        let mut responses = Vec::new();
+        while let Some(response) = read_message(&mut reader).unwrap() {
+            responses.push(response.unwrap());
+        }
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[0]["error"]["code"], json!(PARSE_ERROR));
+        assert_eq!(responses[1]["error"]["code"], json!(INVALID_REQUEST));
+        assert_eq!(responses[2]["error"]["code"], json!(INVALID_REQUEST));
+        assert_eq!(responses[3]["id"], json!(1));
+        assert_eq!(responses[3]["result"], Value::Null);
+    }
+
+    #[test]
+    fn position_conversion_works() {
+        let text = "a\n\u{3042}\u{1F600}b\n";
+        let position = LspPosition {
+            line: 1,
+            character: 3,
+        }
+        .to_position(text);
+        assert_eq!(position, Position::new(9, 2, 8));
+        let lsp_position = LspPosition::from_position(text, position);
+        assert_eq!((lsp_position.line, lsp_position.character), (1, 3));
+    }
+}
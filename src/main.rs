@@ -1,9 +1,15 @@
 use anyhow::Context;
 use clap::{CommandFactory as _, Parser};
+use efmt::config::{Config, Override};
+use efmt::encoding::Encoding;
 use efmt::files::RebarConfigValue;
 use efmt::items::ModuleOrConfig;
+use efmt::line_ending::LineEnding;
+use efmt::range::FormatRange;
+use efmt::report::{FileReport, FileStatus, ReportFormat};
 use env_logger::Env;
 use rayon::iter::{IntoParallelIterator as _, ParallelIterator};
+use std::collections::HashMap;
 use std::io::Read as _;
 use std::io::Write as _;
 use std::path::{Path, PathBuf};
@@ -11,15 +17,16 @@ use std::path::{Path, PathBuf};
 const DEFAULT_CACHE_DIR: &str = ".efmt/cache";
 
 /// Erlang Code Formatter.
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 #[clap(about, version)]
 struct Opt {
     /// Maximum line length.
     ///
     /// Note that this is a soft limit. That is, some lines could exceed the limit after formatting.
     /// Besides, this limit doesn't apply to comments.
-    #[clap(long, default_value_t = 120)]
-    print_width: usize,
+    /// [default: 120]
+    #[clap(long)]
+    print_width: Option<usize>,
 
     /// Checks if input is formatted correctly.
     ///
@@ -27,6 +34,12 @@ struct Opt {
     #[clap(long, short)]
     check: bool,
 
+    /// Outputs the check results in the specified format instead of diffs.
+    ///
+    /// Possible values are `json`, `checkstyle` (Checkstyle XML) and `sarif` (SARIF v2.1.0).
+    #[clap(long, value_name = "FORMAT", requires = "check")]
+    report_format: Option<ReportFormat>,
+
     /// Overwrites input file with the formatted text.
     #[clap(long, short, conflicts_with = "check")]
     write: bool,
@@ -51,6 +64,13 @@ struct Opt {
     #[clap(short = 'I', long = "include-search-dir")]
     include_dirs: Vec<PathBuf>,
 
+    /// Defines a macro like `erlc -D`.
+    ///
+    /// `NAME` is defined as `true` if `VALUE` is omitted.
+    /// The macros are visible from the beginning of each target file.
+    #[clap(short = 'D', long = "define", value_name = "NAME[=VALUE]")]
+    macro_defines: Vec<String>,
+
     /// Format target files.
     ///
     /// `-` means the standard input.
@@ -68,38 +88,226 @@ struct Opt {
     #[clap(long)]
     disable_include: bool,
 
+    /// The root directory of Erlang/OTP (i.e., `code:root_dir()`) used to resolve `-include_lib` directives.
+    ///
+    /// If omitted, it is detected from the location of `erl` command.
+    /// The applications are searched in the directories of `ERL_LIBS` envvar and `OTP_ROOT/lib/`.
+    #[clap(long, value_name = "DIR")]
+    otp_root: Option<PathBuf>,
+
+    /// The OTP release that the target files are compiled with (e.g., `26`).
+    ///
+    /// This determines the features enabled by default (e.g., `maybe_expr` is enabled since OTP 27).
+    /// If omitted, it is detected from `OTP_ROOT/releases/`.
+    #[clap(long, value_name = "RELEASE")]
+    otp_release: Option<u32>,
+
+    /// Line ending of the formatted text.
+    ///
+    /// Possible values are `lf`, `crlf` and `auto` (the same line ending as the first line of each file).
+    /// [default: auto]
+    #[clap(long, value_name = "LF|CRLF|AUTO")]
+    newline: Option<LineEnding>,
+
+    /// Enables a feature like `erlc -enable-feature` (e.g., `maybe_expr`).
+    #[clap(long, value_name = "FEATURE")]
+    enable_feature: Vec<String>,
+
+    /// Disables a feature like `erlc -disable-feature` (e.g., `maybe_expr`).
+    #[clap(long, value_name = "FEATURE")]
+    disable_feature: Vec<String>,
+
+    /// Runs `erl` command to resolve an `-include_lib` directive if the application is not found
+    /// in the library directories.
+    #[clap(long)]
+    include_lib_erl_fallback: bool,
+
     /// Where to save the caches for the macro definitions collected during processing `-include` or `-include_lib` directives
-    /// [default: .efmt/cache]
+    /// [default: .efmt/cache under the directory containing `rebar.config` or the configuration file,
+    /// or under the current directory if neither is found]
     #[clap(long)]
     include_cache_dir: Option<PathBuf>,
 
+    /// Limits the total size of the include cache entries (e.g., `100M`).
+    ///
+    /// The oldest entries are removed when the limit is exceeded.
+    /// `K`, `M` and `G` suffixes are available.
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_size))]
+    include_cache_max_size: Option<u64>,
+
     /// Disables include cache.
     #[clap(long)]
     disable_include_cache: bool,
 
+    /// Disables the cache of the files formatted correctly.
+    ///
+    /// By default, efmt records the files formatted correctly under the include cache directory,
+    /// and skips re-formatting them if neither the files nor the files included by them are changed.
+    /// This cache is enabled only if the cache directory is anchored at a project root
+    /// (i.e., `rebar.config` or a configuration file is found) or is explicitly specified.
+    #[clap(long)]
+    disable_format_cache: bool,
+
     /// Disables formatting by default.
     /// efmt behaves as if there is a "% @efmt:off" comment at the head of the each target file.
     #[clap(long)]
     default_off: bool,
 
+    /// Keeps the forms that could not be parsed as-is and formats the other forms.
+    ///
+    /// The unparsable forms are reported as warnings.
+    #[clap(long)]
+    error_tolerant: bool,
+
+    /// Checks that formatting the formatted text again yields the same text.
+    ///
+    /// This is a safeguard against efmt bugs and makes formatting slower.
+    #[clap(long)]
+    verify_idempotency: bool,
+
     /// Disables mimicking the behavior of `rebar3 efmt`.
     #[clap(long)]
     disable_rebar3_efmt_mode: bool,
+
+    /// Merges the `erl_opts` of the specified rebar3 profile into the top-level ones (e.g., `--profile test`).
+    ///
+    /// This option can be specified multiple times and the profiles are applied in that order.
+    /// The `_build/PROFILE/lib/` directory is also searched for the applications of `-include_lib` directives.
+    #[clap(long, value_name = "PROFILE")]
+    profile: Vec<String>,
+
+    /// Evaluates `rebar.config.script` (if exists) by using `erl` command to obtain the rebar3 configuration.
+    ///
+    /// Without this flag, only the static `rebar.config` file is read.
+    #[clap(long)]
+    eval_rebar_config_script: bool,
+
+    /// Formats only the top-level forms that intersect with the specified line range (1-origin, inclusive).
+    ///
+    /// The text outside of the forms is kept as-is.
+    /// Note that this option is only applicable to Erlang modules (`rebar.config` and `*.app.src` files are entirely formatted).
+    #[clap(long, value_name = "START:END")]
+    range: Option<FormatRange>,
+
+    /// Formats only the top-level forms that intersect with the lines changed since the specified git revision.
+    ///
+    /// The changed lines are obtained by `$ git diff REF`, and untracked files are regarded as entirely changed.
+    /// If no files are specified and any of `-c`, `-w` or `--show-files` options is specified,
+    /// the changed files are used as the default target files.
+    /// Note that non-module files (i.e., `rebar.config` and `*.app.src`) are entirely formatted if they are changed.
+    #[clap(long, value_name = "REF", conflicts_with = "range")]
+    changed_since: Option<String>,
+
+    #[clap(skip)]
+    changed_lines: Option<HashMap<PathBuf, Vec<FormatRange>>>,
+
+    /// Loads options from the specified configuration file.
+    ///
+    /// If omitted, `.efmt.toml` or `efmt.config` is searched from the current directory up to the root.
+    /// The options specified in the command line take precedence over the ones in the configuration file,
+    /// and the latter take precedence over the ones in the `{efmt, [...]}` entry of `rebar.config`.
+    #[clap(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// The options loaded from `rebar.config` and the configuration file.
+    #[clap(skip)]
+    loaded_config: Config,
+
+    /// Starts a Language Server Protocol server that communicates over the standard input and output.
+    ///
+    /// The server supports `textDocument/formatting`, `textDocument/rangeFormatting` and
+    /// `textDocument/onTypeFormatting` requests.
+    #[clap(
+        long,
+        conflicts_with = "check",
+        conflicts_with = "write",
+        conflicts_with = "show-files"
+    )]
+    lsp: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Command {
+    /// Manages the cache directory (see `--include-cache-dir` option).
+    Cache {
+        #[clap(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Debug, Clone, clap::Subcommand)]
+enum CacheAction {
+    /// Shows the number of entries and the total size of the cache.
+    Stats,
+
+    /// Removes all the cache entries.
+    Clear,
+
+    /// Removes the cache entries that are created by another version of efmt or refer to removed or modified files.
+    Prune {
+        /// Also removes the entries older than the specified days.
+        #[clap(long, value_name = "DAYS")]
+        max_age_days: Option<u64>,
+    },
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let unit = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown size unit: {unit:?}")),
+    };
+    n.parse::<u64>()
+        .map(|n| n.saturating_mul(unit))
+        .map_err(|e| e.to_string())
 }
 
 impl Opt {
     fn collect_default_files_if_need(&mut self) -> anyhow::Result<()> {
+        let mut changed_files = None;
+        if let Some(since) = &self.changed_since {
+            let files = efmt::files::collect_changed_files(since)?;
+            self.changed_lines = Some(
+                files
+                    .iter()
+                    .filter_map(|(path, ranges)| {
+                        Some((std::fs::canonicalize(path).ok()?, ranges.clone()))
+                    })
+                    .collect(),
+            );
+            changed_files = Some(files.into_iter().map(|(path, _)| path).collect::<Vec<_>>());
+        }
+
         if !self.files.is_empty() || !(self.check || self.write || self.show_files) {
             return Ok(());
         }
 
-        self.files = efmt::files::collect_default_target_files()?
+        let files = if let Some(files) = changed_files {
+            files
+        } else {
+            efmt::files::collect_default_target_files()?
+        };
+        let mut exclude_files = self.exclude_files.clone();
+        for pattern in &self.loaded_config.exclude_files {
+            exclude_files.push(
+                regex::Regex::new(pattern)
+                    .with_context(|| format!("invalid `exclude_files` pattern: {pattern:?}"))?,
+            );
+        }
+        self.files = files
             .into_iter()
             .filter(|path| {
                 let path = path.to_string_lossy();
-                self.exclude_files
-                    .iter()
-                    .all(|regex| !regex.is_match(&path))
+                exclude_files.iter().all(|regex| !regex.is_match(&path))
             })
             .collect::<Vec<_>>();
         if !self.files.is_empty() && !self.show_files {
@@ -115,34 +323,164 @@ impl Opt {
         Ok(())
     }
 
-    fn to_format_options(&self) -> efmt::Options {
+    /// Returns the ranges to be formatted in `file` (`None` means the whole file).
+    fn format_ranges(&self, file: &Path) -> Option<Vec<FormatRange>> {
+        // Non-module files (i.e., `rebar.config` and `*.app.src`) are always entirely formatted.
+        let is_module = file.to_str() == Some("-")
+            || file.extension().is_some_and(|x| x == "erl" || x == "hrl");
+        if let Some(range) = self.range {
+            return is_module.then(|| vec![range]);
+        }
+
+        let changed_lines = self.changed_lines.as_ref()?;
+        let ranges = std::fs::canonicalize(file)
+            .ok()
+            .and_then(|file| changed_lines.get(&file).cloned())
+            .unwrap_or_default();
+        if !ranges.is_empty() && !is_module {
+            None
+        } else {
+            Some(ranges)
+        }
+    }
+
+    fn cache_dir(&self, config: &Config) -> PathBuf {
+        self.include_cache_dir
+            .clone()
+            .or_else(|| config.include_cache_dir.clone())
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR))
+    }
+
+    fn is_parallel(&self) -> bool {
+        self.parallel || self.loaded_config.parallel == Some(true)
+    }
+
+    /// Makes the formatting options for `file` (`None` means the standard input).
+    ///
+    /// The precedence is: command line > overrides for `file` > configuration file > `rebar.config`.
+    fn to_format_options(&self, file: Option<&Path>) -> efmt::Options {
+        let config = match file {
+            Some(file) => self.loaded_config.resolve_for(file),
+            None => self.loaded_config.clone(),
+        };
+        let flag = |cli: bool, config: Option<bool>| cli || config == Some(true);
+
         let mut format_options = efmt::Options::new()
-            .max_columns(self.print_width)
-            .include_dirs(self.include_dirs.clone());
-
-        if !self.disable_include_cache {
-            format_options = format_options.include_cache_dir(
-                self.include_cache_dir
-                    .clone()
-                    .unwrap_or_else(|| PathBuf::from(DEFAULT_CACHE_DIR)),
-            );
+            .max_columns(
+                self.print_width
+                    .or(config.print_width)
+                    .unwrap_or(efmt::Options::DEFAULT_MAX_COLUMNS),
+            )
+            .include_dirs(
+                self.include_dirs
+                    .iter()
+                    .chain(&config.include_search_dirs)
+                    .collect(),
+            )
+            .include_lib_dirs(config.lib_dirs.iter().collect());
+        let otp_root = self.otp_root.as_ref().or(config.otp_root.as_ref());
+        if let Some(dir) = otp_root {
+            format_options = format_options.otp_root(dir);
+        }
+        if let Some(release) = self
+            .otp_release
+            .or(config.otp_release)
+            .or_else(|| efmt::files::find_otp_release(otp_root.map(PathBuf::as_path)))
+        {
+            format_options = format_options.otp_release(release);
+        }
+        if let Some(line_ending) = self.newline.or(config.newline) {
+            format_options = format_options.line_ending(line_ending);
+        }
+        for (names, enabled) in [
+            (&config.enable_features, true),
+            (&config.disable_features, false),
+            (&self.enable_feature, true),
+            (&self.disable_feature, false),
+        ] {
+            for name in names {
+                format_options = format_options.feature(name, enabled);
+            }
+        }
+        if flag(
+            self.include_lib_erl_fallback,
+            config.include_lib_erl_fallback,
+        ) {
+            format_options = format_options.include_lib_erl_fallback();
+        }
+
+        let cache_dir = self.cache_dir(&config);
+        if !flag(self.disable_include_cache, config.disable_include_cache) {
+            format_options = format_options.include_cache_dir(&cache_dir);
+            if let Some(n) = self
+                .include_cache_max_size
+                .or(config.include_cache_max_size)
+            {
+                format_options = format_options.include_cache_max_size(n);
+            }
+        }
+        let is_cache_dir_anchored =
+            self.include_cache_dir.is_some() || config.include_cache_dir.is_some();
+        if is_cache_dir_anchored && !flag(self.disable_format_cache, config.disable_format_cache) {
+            format_options = format_options.format_cache_dir(&cache_dir);
         }
-        if self.disable_include {
+        if flag(self.disable_include, config.disable_include) {
             format_options = format_options.disable_include();
         }
-        if self.default_off {
+        if flag(self.default_off, config.default_off) {
             format_options = format_options.default_off();
         }
+        if flag(self.error_tolerant, config.error_tolerant) {
+            format_options = format_options.error_tolerant();
+        }
+        if flag(self.verify_idempotency, config.verify_idempotency) {
+            format_options = format_options.verify_idempotency(true);
+        }
+        for define in config.defines.iter().chain(&self.macro_defines) {
+            if let Some((name, value)) = define.split_once('=') {
+                format_options = format_options.define_macro(name, Some(value));
+            } else {
+                format_options = format_options.define_macro(define, None);
+            }
+        }
 
         format_options
     }
 
+    /// Makes the formatting options for a target file (`-` means the standard input).
+    fn file_format_options(&self, file: &Path) -> efmt::Options {
+        self.to_format_options((file.to_str() != Some("-")).then_some(file))
+    }
+
+    /// Loads a configuration file that takes precedence over `rebar.config`.
+    fn load_config_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let config = Config::load(path)
+            .with_context(|| format!("failed to load configuration file: {path:?}"))?;
+        self.loaded_config = std::mem::take(&mut self.loaded_config).merge(config);
+
+        // include cache dir (unless already anchored at the rebar3 project root)
+        if self.loaded_config.include_cache_dir.is_none() {
+            let config_dir = path.parent().unwrap_or(Path::new(""));
+            self.loaded_config.include_cache_dir = Some(config_dir.join(DEFAULT_CACHE_DIR));
+        }
+        Ok(())
+    }
+
     fn enable_rebar3_efmt_mode(&mut self, rebar_config_dir: PathBuf) -> anyhow::Result<()> {
         // rebar.config
         let rebar_config_path = rebar_config_dir.join("rebar.config");
-        for value in efmt::files::load_rebar_config(&rebar_config_path)
-            .with_context(|| format!("failed to load rebar.config file: {rebar_config_path:?}"))?
-        {
+        let script_path = rebar_config_dir.join("rebar.config.script");
+        let values = if self.eval_rebar_config_script && script_path.exists() {
+            log::debug!("evaluating {script_path:?}");
+            efmt::files::eval_rebar_config_script(&rebar_config_dir)
+                .with_context(|| format!("failed to evaluate {script_path:?}"))?
+        } else {
+            efmt::files::load_rebar_config(&rebar_config_path).with_context(|| {
+                format!("failed to load rebar.config file: {rebar_config_path:?}")
+            })?
+        };
+        let mut config = Config::default();
+        for value in &values {
             if let RebarConfigValue::Tuple(item) = value {
                 if item.len() != 2 {
                     continue;
@@ -151,116 +489,146 @@ impl Opt {
                     (RebarConfigValue::Atom(key), RebarConfigValue::List(items))
                         if key == "erl_opts" =>
                     {
-                        self.handle_rebar_config_erl_opts(items, &rebar_config_dir);
+                        Self::handle_rebar_config_erl_opts(&mut config, items);
                     }
                     (RebarConfigValue::Atom(key), RebarConfigValue::List(items))
                         if key == "efmt" =>
                     {
-                        self.handle_rebar_config_efmt(items, &rebar_config_dir);
+                        log::debug!("found efmt options in rebar.config: {}", item[1]);
+                        config = config.merge(Config::from_terms(items));
                     }
                     _ => {}
                 }
             }
         }
 
-        // ERL_LIBS
-        let libs_path = rebar_config_dir.join("_build/default/lib/");
-        if let Some(paths) = std::env::var_os("ERL_LIBS") {
-            let paths = std::env::split_paths(&paths).chain(std::iter::once(libs_path));
-            let paths = std::env::join_paths(paths)?;
-            std::env::set_var("ERL_LIBS", paths);
-        } else {
-            std::env::set_var("ERL_LIBS", libs_path);
+        self.handle_rebar_config_profiles(&mut config, &values);
+
+        // umbrella applications
+        for app in efmt::files::find_project_apps(&rebar_config_dir, &values) {
+            log::debug!("found an umbrella application: {app:?}");
+            let lib_dir = app.parent().map(Path::to_path_buf).unwrap_or_default();
+            if !config.lib_dirs.contains(&lib_dir) {
+                config.lib_dirs.push(lib_dir);
+            }
+
+            let app_rebar_config_path = rebar_config_dir.join(&app).join("rebar.config");
+            if !app_rebar_config_path.exists() {
+                continue;
+            }
+            let app_values = match efmt::files::load_rebar_config(&app_rebar_config_path) {
+                Ok(values) => values,
+                Err(e) => {
+                    log::warn!("failed to load rebar.config file: {app_rebar_config_path:?}: {e}");
+                    continue;
+                }
+            };
+            let mut app_config = Config::default();
+            for value in &app_values {
+                if let Some(("erl_opts", RebarConfigValue::List(items))) = value.as_kv_tuple() {
+                    Self::handle_rebar_config_erl_opts(&mut app_config, items);
+                }
+            }
+            self.handle_rebar_config_profiles(&mut app_config, &app_values);
+
+            let mut include_search_dirs = app_config.include_search_dirs;
+            if !include_search_dirs.is_empty() {
+                // Specifying include directories disables the default ones, so adds them explicitly.
+                include_search_dirs
+                    .splice(0..0, [PathBuf::from("include/"), PathBuf::from("src/")]);
+            }
+            let mut app_override = Override::default();
+            app_override.files = vec!["**".parse()?];
+            app_override.include_search_dirs = include_search_dirs;
+            app_override.defines = app_config.defines;
+            config = config.merge(
+                Config {
+                    overrides: vec![app_override],
+                    ..Default::default()
+                }
+                .with_base_dir(rebar_config_dir.join(&app)),
+            );
         }
-        if let Some(paths) = std::env::var_os("ERL_LIBS") {
-            log::debug!("set 'ERL_LIBS' envvar to {paths:?}");
+
+        // _build/*/lib/ (searched after the umbrella applications)
+        for dir in efmt::files::find_build_lib_dirs(&rebar_config_dir, &self.profile) {
+            if !config.lib_dirs.contains(&dir) {
+                config.lib_dirs.push(dir);
+            }
         }
 
         // include cache dir
-        if self.include_cache_dir.is_none() {
-            self.include_cache_dir = Some(rebar_config_dir.join(DEFAULT_CACHE_DIR));
+        if config.include_cache_dir.is_none() {
+            config.include_cache_dir = Some(PathBuf::from(DEFAULT_CACHE_DIR));
         }
+        self.loaded_config = config
+            .with_base_dir(&rebar_config_dir)
+            .merge(std::mem::take(&mut self.loaded_config));
 
         Ok(())
     }
 
-    fn handle_rebar_config_erl_opts(
-        &mut self,
-        items: &[RebarConfigValue],
-        rebar_config_dir: &Path,
-    ) {
-        for item in items {
-            if let RebarConfigValue::Tuple(kv) = item {
-                if kv.len() != 2 {
-                    continue;
-                }
-                match (&kv[0], &kv[1]) {
-                    (RebarConfigValue::Atom(k), RebarConfigValue::String(v)) if k == "i" => {
-                        log::debug!("found `{{i, {v:?}}}` in rebar.config");
-                        self.include_dirs.push(rebar_config_dir.join(v));
-                    }
-                    _ => {}
+    fn handle_rebar_config_profiles(&self, config: &mut Config, values: &[RebarConfigValue]) {
+        let profiles = values
+            .iter()
+            .find_map(|x| match x.as_kv_tuple() {
+                Some(("profiles", RebarConfigValue::List(items))) => Some(
+                    items
+                        .iter()
+                        .filter_map(|x| x.as_kv_tuple())
+                        .collect::<Vec<_>>(),
+                ),
+                _ => None,
+            })
+            .unwrap_or_default();
+        for name in &self.profile {
+            let Some((_, RebarConfigValue::List(items))) = profiles.iter().find(|(k, _)| k == name)
+            else {
+                log::debug!("profile {name:?} is not found in rebar.config");
+                continue;
+            };
+            log::debug!("applying profile {name:?}");
+            for item in items {
+                if let Some(("erl_opts", RebarConfigValue::List(erl_opts))) = item.as_kv_tuple() {
+                    Self::handle_rebar_config_erl_opts(config, erl_opts);
                 }
             }
         }
     }
 
-    fn handle_rebar_config_efmt(&mut self, items: &[RebarConfigValue], rebar_config_dir: &Path) {
-        let matches = Self::command().get_matches();
+    fn handle_rebar_config_erl_opts(config: &mut Config, items: &[RebarConfigValue]) {
         for item in items {
-            log::debug!("found an efmt option in rebar.config: {item:?}");
-            if let RebarConfigValue::Atom(k) = item {
-                match k.as_str() {
-                    "parallel" => {
-                        self.parallel = true;
-                        continue;
-                    }
-                    "disable_include" => {
-                        self.disable_include = true;
-                        continue;
-                    }
-                    "disable_include_cache" => {
-                        self.disable_include_cache = true;
-                        continue;
-                    }
-                    "default_off" => {
-                        self.default_off = true;
-                        continue;
+            if let RebarConfigValue::Tuple(kv) = item {
+                match kv.as_slice() {
+                    [RebarConfigValue::Atom(k), dir] if k == "i" && dir.as_str().is_some() => {
+                        log::debug!("found `{item}` in rebar.config");
+                        config
+                            .include_search_dirs
+                            .extend(dir.as_str().map(PathBuf::from));
                     }
-                    _ => {}
-                }
-            } else if let Some((k, v)) = item.as_kv_tuple() {
-                match k {
-                    "print_width" => {
-                        if let RebarConfigValue::Integer(v) = v {
-                            if matches.occurrences_of("print-width") == 0 {
-                                self.print_width = *v as usize;
-                            } else {
-                                log::debug!("ignored {k:?} option in rebar.config in favor of command line arg");
-                            }
-                            continue;
-                        }
+                    [RebarConfigValue::Atom(k), name @ RebarConfigValue::Atom(_)] if k == "d" => {
+                        log::debug!("found `{item}` in rebar.config");
+                        config.defines.push(name.to_string());
                     }
-                    "I" | "include_search_dir" => {
-                        if let RebarConfigValue::String(v) = v {
-                            self.include_dirs.push(rebar_config_dir.join(v));
-                            continue;
-                        }
+                    [RebarConfigValue::Atom(k), name @ RebarConfigValue::Atom(_), value]
+                        if k == "d" =>
+                    {
+                        log::debug!("found `{item}` in rebar.config");
+                        config.defines.push(format!("{name}={value}"));
                     }
-                    "include_cache_dir" => {
-                        if let RebarConfigValue::String(v) = v {
-                            if matches.occurrences_of("include-cache-dir") == 0 {
-                                self.include_cache_dir = Some(rebar_config_dir.join(v));
-                            } else {
-                                log::debug!("ignored {k:?} option in rebar.config in favor of command line arg");
-                            }
-                            continue;
+                    [RebarConfigValue::Atom(k), RebarConfigValue::Atom(name), RebarConfigValue::Atom(v)]
+                        if k == "feature" && (v == "enable" || v == "disable") =>
+                    {
+                        log::debug!("found `{item}` in rebar.config");
+                        if v == "enable" {
+                            config.enable_features.push(name.clone());
+                        } else {
+                            config.disable_features.push(name.clone());
                         }
                     }
                     _ => {}
                 }
             }
-            log::warn!("found an unhandled efmt option in rebar.config: {item:?}");
         }
     }
 }
@@ -271,6 +639,10 @@ fn main() -> anyhow::Result<()> {
     let loglevel = if opt.verbose { "debug" } else { "info" };
     env_logger::Builder::from_env(Env::default().default_filter_or(loglevel)).init();
 
+    if opt.lsp {
+        return run_language_server(opt);
+    }
+
     if !opt.disable_rebar3_efmt_mode {
         if let Some(rebar_config_dir) = efmt::files::find_rebar_config_dir() {
             log::debug!("rebar.config file found: dir={rebar_config_dir:?}");
@@ -279,8 +651,24 @@ fn main() -> anyhow::Result<()> {
             log::debug!("rebar.config file not found");
         }
     }
+    if let Some(config_file) = opt.config.clone().or_else(efmt::config::find_config_file) {
+        log::debug!("configuration file found: {config_file:?}");
+        opt.load_config_file(&config_file)?;
+    }
+
+    if let Some(Command::Cache { action }) = &opt.command {
+        return run_cache_command(&opt, action);
+    }
 
     opt.collect_default_files_if_need()?;
+    if opt.files.is_empty()
+        && opt.changed_lines.is_some()
+        && (opt.check || opt.write || opt.show_files)
+    {
+        // Nothing to do (e.g., a CI run on a branch without Erlang changes).
+        eprintln!("no changed files");
+        return Ok(());
+    }
     if opt.files.is_empty() {
         Opt::command().print_help()?;
         println!();
@@ -301,55 +689,154 @@ fn main() -> anyhow::Result<()> {
     }
 }
 
+fn run_cache_command(opt: &Opt, action: &CacheAction) -> anyhow::Result<()> {
+    let cache_dir = opt.cache_dir(&opt.loaded_config);
+    let print_stats = |stats: efmt::cache::CacheStats| {
+        println!(
+            "Include cache: {} entries ({} bytes)",
+            stats.include_entries, stats.include_bytes
+        );
+        println!(
+            "Format cache:  {} entries ({} bytes)",
+            stats.format_entries, stats.format_bytes
+        );
+    };
+    match action {
+        CacheAction::Stats => {
+            println!("Cache directory: {}", cache_dir.display());
+            print_stats(efmt::cache::stats(&cache_dir));
+        }
+        CacheAction::Clear => {
+            let removed = efmt::cache::clear(&cache_dir)
+                .with_context(|| format!("failed to clear the cache directory {cache_dir:?}"))?;
+            println!("Removed from {}:", cache_dir.display());
+            print_stats(removed);
+        }
+        CacheAction::Prune { max_age_days } => {
+            let max_age =
+                max_age_days.map(|days| std::time::Duration::from_secs(days * 24 * 60 * 60));
+            let removed = efmt::cache::prune(&cache_dir, max_age)
+                .with_context(|| format!("failed to prune the cache directory {cache_dir:?}"))?;
+            println!("Removed from {}:", cache_dir.display());
+            print_stats(removed);
+        }
+    }
+    Ok(())
+}
+
+fn run_language_server(opt: Opt) -> anyhow::Result<()> {
+    // The options (including the warm include cache) are kept for each pair of
+    // a rebar3 project and a configuration file.
+    let mut workspaces =
+        HashMap::<(Option<PathBuf>, Option<PathBuf>), (Opt, efmt::parse::IncludeMemoryCache)>::new(
+        );
+    let server = efmt::lsp::LanguageServer::new(move |path: Option<&Path>| {
+        let dir = path.and_then(|p| p.parent());
+        let rebar_config_dir = if opt.disable_rebar3_efmt_mode {
+            None
+        } else {
+            dir.and_then(efmt::files::find_rebar_config_dir_from)
+        };
+        let config_file = opt
+            .config
+            .clone()
+            .or_else(|| dir.and_then(efmt::config::find_config_file_from));
+        let (workspace_opt, cache) = workspaces
+            .entry((rebar_config_dir.clone(), config_file.clone()))
+            .or_insert_with(|| {
+                let mut opt = opt.clone();
+                if let Some(dir) = rebar_config_dir {
+                    log::debug!("rebar.config file found: dir={dir:?}");
+                    if let Err(e) = opt.enable_rebar3_efmt_mode(dir) {
+                        log::warn!("{e:?}");
+                    }
+                }
+                if let Some(config_file) = config_file {
+                    log::debug!("configuration file found: {config_file:?}");
+                    if let Err(e) = opt.load_config_file(&config_file) {
+                        log::warn!("{e:?}");
+                    }
+                }
+                (opt, efmt::parse::IncludeMemoryCache::new())
+            });
+        workspace_opt
+            .to_format_options(path)
+            .include_memory_cache(cache.clone())
+    });
+    server.run(std::io::stdin().lock(), std::io::stdout().lock())
+}
+
 fn format_file<P: AsRef<Path>>(
     format_options: &efmt::Options,
     path: P,
-) -> anyhow::Result<(String, String)> {
-    let original = std::fs::read_to_string(&path)?;
-    let formatted = format_options
-        .clone()
-        .format_file::<ModuleOrConfig, _>(path)?;
-    Ok((original, formatted))
+    ranges: Option<&[FormatRange]>,
+) -> anyhow::Result<(String, String, Encoding)> {
+    let (original, encoding) = efmt::encoding::read_file(&path)?;
+    let formatted = match ranges {
+        Some([]) => original.clone(),
+        Some(ranges) => {
+            let edits = format_options
+                .clone()
+                .format_file_text_ranges(path, &original, ranges)?;
+            efmt::range::apply_edits(&original, &edits)
+        }
+        None => format_options
+            .clone()
+            .format_file_text::<ModuleOrConfig, _>(path, &original)?,
+    };
+    Ok((original, formatted, encoding))
 }
 
-fn format_stdin(format_options: &efmt::Options) -> anyhow::Result<(String, String)> {
-    let mut original = String::new();
-    std::io::stdin().lock().read_to_string(&mut original)?;
-    let formatted = format_options
-        .clone()
-        .format_text::<ModuleOrConfig>(&original)?;
-    Ok((original, formatted))
+fn format_stdin(
+    format_options: &efmt::Options,
+    ranges: Option<&[FormatRange]>,
+) -> anyhow::Result<(String, String, Encoding)> {
+    let mut bytes = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut bytes)?;
+    let (original, encoding) = efmt::encoding::decode(bytes)?;
+    let formatted = match ranges {
+        Some([]) => original.clone(),
+        Some(ranges) => {
+            let edits = format_options
+                .clone()
+                .format_text_ranges(&original, ranges)?;
+            efmt::range::apply_edits(&original, &edits)
+        }
+        None => format_options
+            .clone()
+            .format_text::<ModuleOrConfig>(&original)?,
+    };
+    Ok((original, formatted, encoding))
 }
 
+/// Formats a file (or the standard input) and returns the original text, the formatted text and the encoding of the file.
 fn format_file_or_stdin<P: AsRef<Path>>(
     format_options: &efmt::Options,
     path: P,
-) -> anyhow::Result<(String, String)> {
-    let (original, formatted) = if path.as_ref().to_str() == Some("-") {
-        format_stdin(format_options)
+    ranges: Option<&[FormatRange]>,
+) -> anyhow::Result<(String, String, Encoding)> {
+    if path.as_ref().to_str() == Some("-") {
+        format_stdin(format_options, ranges)
     } else {
-        format_file(format_options, &path)
-    }?;
-    validate_formatted_text(path, &original, &formatted).context(concat!(
-        "Found a token mismatch between the original text ",
-        "and the formatted one (maybe efmt bug)"
-    ))?;
-    Ok((original, formatted))
+        format_file(format_options, &path, ranges)
+    }
 }
 
 fn format_files(opt: &Opt) -> anyhow::Result<()> {
-    let format_options = opt.to_format_options();
-
-    fn do_format(opt: &Opt, format_options: &efmt::Options, file: &Path) -> anyhow::Result<()> {
-        match format_file_or_stdin(format_options, file) {
+    fn do_format(opt: &Opt, file: &Path) -> anyhow::Result<()> {
+        let format_options = opt.file_format_options(file);
+        match format_file_or_stdin(&format_options, file, opt.format_ranges(file).as_deref()) {
             Err(e) => {
                 log::error!("Failed to format {:?}\n{:?}", file, e);
                 Err(e)
             }
-            Ok((original, formatted)) => {
+            Ok((original, formatted, encoding)) => {
                 if opt.write {
                     if original != formatted {
-                        let result = overwrite(file, &formatted);
+                        let result = encoding
+                            .encode(&formatted)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|bytes| overwrite(file, &bytes));
                         if let Err(e) = &result {
                             log::error!("Failed to write formatted text to {:?}: {:?}", file, e);
                         } else {
@@ -360,23 +847,24 @@ fn format_files(opt: &Opt) -> anyhow::Result<()> {
                         Ok(())
                     }
                 } else {
-                    print!("{}", formatted);
+                    let bytes = encoding.encode(&formatted)?;
+                    std::io::stdout().lock().write_all(&bytes)?;
                     Ok(())
                 }
             }
         }
     }
 
-    let error_files = if opt.parallel {
+    let error_files = if opt.is_parallel() {
         opt.files
             .clone()
             .into_par_iter()
-            .filter(|file| do_format(opt, &format_options, file).is_err())
+            .filter(|file| do_format(opt, file).is_err())
             .collect::<Vec<_>>()
     } else {
         opt.files
             .iter()
-            .filter(|file| do_format(opt, &format_options, file).is_err())
+            .filter(|file| do_format(opt, file).is_err())
             .cloned()
             .collect::<Vec<_>>()
     };
@@ -399,42 +887,50 @@ fn format_files(opt: &Opt) -> anyhow::Result<()> {
 }
 
 fn check_files(opt: &Opt) -> anyhow::Result<()> {
-    let format_options = opt.to_format_options();
-
-    fn do_check(format_options: &efmt::Options, file: &Path) -> bool {
-        match format_file_or_stdin(format_options, file) {
+    fn do_check(opt: &Opt, file: &Path) -> FileReport {
+        let format_options = opt.file_format_options(file);
+        match format_file_or_stdin(&format_options, file, opt.format_ranges(file).as_deref()) {
             Err(e) => {
                 log::error!("Failed to format {:?}\n{:?}", file, e);
-                false
+                FileReport::error(file, &e)
             }
-            Ok((original, formatted)) => {
-                if original == formatted {
+            Ok((original, formatted, _)) => {
+                let report = FileReport::new(file, &original, &formatted);
+                if report.status == FileStatus::Formatted {
                     log::info!("{:?} is already formatted correctly.", file);
-                    true
                 } else {
-                    let diff = efmt::diff::text_diff(&original, &formatted, file);
-                    println!("{}", diff);
+                    if opt.report_format.is_none() {
+                        let diff = efmt::diff::text_diff(&original, &formatted, file);
+                        println!("{}", diff);
+                    }
                     log::info!("{:?} is not formatted correctly.", file);
-                    false
                 }
+                report
             }
         }
     }
 
-    let unformatted_files = if opt.parallel {
+    let reports = if opt.is_parallel() {
         opt.files
             .clone()
             .into_par_iter()
-            .filter(|file| !do_check(&format_options, file))
+            .map(|file| do_check(opt, &file))
             .collect::<Vec<_>>()
     } else {
         opt.files
             .iter()
-            .filter(|file| !do_check(&format_options, file))
-            .cloned()
+            .map(|file| do_check(opt, file))
             .collect::<Vec<_>>()
     };
+    if let Some(format) = opt.report_format {
+        efmt::report::write_report(std::io::stdout().lock(), format, &reports)?;
+    }
 
+    let unformatted_files = reports
+        .iter()
+        .filter(|report| report.status != FileStatus::Formatted)
+        .map(|report| &report.path)
+        .collect::<Vec<_>>();
     if !unformatted_files.is_empty() {
         eprintln!();
         anyhow::bail!(
@@ -451,88 +947,53 @@ fn check_files(opt: &Opt) -> anyhow::Result<()> {
     Ok(())
 }
 
-fn validate_formatted_text<P: AsRef<Path>>(
-    path: P,
-    original: &str,
-    formatted: &str,
-) -> anyhow::Result<()> {
-    use erl_tokenize::{PositionRange as _, Result, Token, Tokenizer};
-
-    fn is_visible_token(t: &Result<Token>) -> bool {
-        !matches!(t, Ok(Token::Whitespace(_)))
-    }
+fn overwrite<P: AsRef<Path>>(path: P, bytes: &[u8]) -> anyhow::Result<()> {
+    let mut temp = tempfile::NamedTempFile::new()?;
+    temp.write_all(bytes)?;
+    temp.persist(path)?;
+    Ok(())
+}
 
-    fn check_extra_token<P: AsRef<Path>>(
-        path: P,
-        text: &str,
-        next_token: Option<Result<Token>>,
-    ) -> anyhow::Result<()> {
-        let next_position = next_token.map(|r| {
-            r.map(|t| t.start_position())
-                .unwrap_or_else(|e| e.position().clone())
-        });
-        if let Some(p) = next_position {
-            anyhow::bail!(
-                "{}",
-                efmt::error::generate_error_message(text, Some(path), p.into(), "extra token")
-            );
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebar3_profiles_work() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path();
+        std::fs::write(
+            root.join("rebar.config"),
+            indoc::indoc! {r#"
+                {erl_opts, [{d, 'BASE'}]}.
+                {profiles, [{test, [{erl_opts, [{d, 'TEST'}, {i, "test/include"}]}]},
+                            {prod, [{erl_opts, [{d, 'PROD'}]}]}]}.
+            "#},
+        )?;
+        for profile in ["default", "test+unknown", "prod"] {
+            std::fs::create_dir_all(root.join("_build").join(profile).join("lib"))?;
         }
-        Ok(())
-    }
 
-    fn text(token: &Token) -> &str {
-        if let Token::Comment(token) = token {
-            token.text().trim_end()
-        } else {
-            token.text()
-        }
-    }
+        // Unknown profiles are ignored except for the name of the combined `_build/` directory.
+        let mut opt = Opt::parse_from(["efmt", "--profile", "test", "--profile", "unknown"]);
+        opt.enable_rebar3_efmt_mode(root.to_path_buf())?;
+        let config = &opt.loaded_config;
+        assert_eq!(config.defines, ["'BASE'", "'TEST'"]);
+        assert_eq!(config.include_search_dirs, [root.join("test/include")]);
+        assert_eq!(
+            config.lib_dirs,
+            ["test+unknown", "default", "prod"].map(|p| root.join("_build").join(p).join("lib/"))
+        );
 
-    let mut tokens0 = Tokenizer::new(original).filter(is_visible_token);
-    let mut tokens1 = Tokenizer::new(formatted).filter(is_visible_token);
-    while let Some(t0) = tokens0.next().transpose().expect("unreachable") {
-        let t1 = match tokens1.next() {
-            Some(Ok(t1)) => t1,
-            Some(Err(e)) => {
-                let reason = e.to_string();
-                let reason_end = reason.find(" (").unwrap_or(reason.len());
-                anyhow::bail!(
-                    "{}",
-                    efmt::error::generate_error_message(
-                        formatted,
-                        Some("<formatted>"),
-                        e.position().clone().into(),
-                        &reason[..reason_end]
-                    )
-                );
-            }
-            None => {
-                return check_extra_token(path, original, Some(Ok(t0)));
-            }
-        };
-        anyhow::ensure!(
-            text(&t0) == text(&t1),
-            "{}\n{}",
-            efmt::error::generate_error_message(
-                original,
-                Some(path),
-                t0.start_position().into(),
-                "expected"
-            ),
-            efmt::error::generate_error_message(
-                formatted,
-                Some("<formatted>"),
-                t1.start_position().into(),
-                "actual"
-            ),
+        let mut opt = Opt::parse_from(["efmt"]);
+        opt.enable_rebar3_efmt_mode(root.to_path_buf())?;
+        let config = &opt.loaded_config;
+        assert_eq!(config.defines, ["'BASE'"]);
+        assert!(config.include_search_dirs.is_empty());
+        assert_eq!(
+            config.lib_dirs,
+            ["default", "prod", "test+unknown"].map(|p| root.join("_build").join(p).join("lib/"))
         );
+        Ok(())
     }
-    check_extra_token("<formatted>", formatted, tokens1.next())
-}
-
-fn overwrite<P: AsRef<Path>>(path: P, text: &str) -> anyhow::Result<()> {
-    let mut temp = tempfile::NamedTempFile::new()?;
-    temp.write_all(text.as_bytes())?;
-    temp.persist(path)?;
-    Ok(())
 }
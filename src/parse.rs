@@ -3,13 +3,16 @@ use crate::span::{Position, Span as _};
 use std::path::PathBuf;
 use std::sync::Arc;
 
-pub use self::include::IncludeOptions;
+pub use self::include::{IncludeMemoryCache, IncludeOptions};
+pub use self::include_source::{FsIncludeSource, IncludeSource, MemoryIncludeSource};
 pub use self::token_stream::TokenStream;
 
 /// A procedural macro to derive [Parse].
 pub use efmt_derive::Parse;
 
+pub(crate) mod ext_token;
 pub(crate) mod include;
+pub(crate) mod include_source;
 pub(crate) mod token_stream;
 
 /// Possible errors.
@@ -63,7 +66,8 @@ impl Error {
         }
     }
 
-    pub(crate) fn position(&self) -> Position {
+    /// Returns the position where the error occurred.
+    pub fn position(&self) -> Position {
         match self {
             Self::UnexpectedEof { position, .. } => *position,
             Self::UnexpectedToken { position, .. } => *position,
@@ -71,14 +75,37 @@ impl Error {
         }
     }
 
-    fn tokenize_error_message(source: &erl_tokenize::Error, text: &Arc<String>) -> String {
+    /// Returns the path of the file where the error occurred.
+    pub fn path(&self) -> Option<&std::path::Path> {
+        match self {
+            Self::UnexpectedEof { path, .. } | Self::UnexpectedToken { path, .. } => {
+                path.as_ref().map(|p| p.as_path())
+            }
+            Self::TokenizeError { source, .. } => source.position().filepath().map(|p| p.as_path()),
+        }
+    }
+
+    /// Returns a short description of the error (without the position information).
+    pub fn reason(&self) -> String {
+        match self {
+            Self::UnexpectedEof { .. } => "unexpected EOF".to_owned(),
+            Self::UnexpectedToken { .. } => "unexpected token".to_owned(),
+            Self::TokenizeError { source, .. } => Self::tokenize_error_reason(source),
+        }
+    }
+
+    fn tokenize_error_reason(source: &erl_tokenize::Error) -> String {
         let source_message = source.to_string();
         let source_message_end = source_message.find(" (").unwrap_or(source_message.len());
+        source_message[..source_message_end].to_owned()
+    }
+
+    fn tokenize_error_message(source: &erl_tokenize::Error, text: &Arc<String>) -> String {
         crate::error::generate_error_message(
             text,
             source.position().filepath(),
             source.position().clone().into(),
-            &source_message[..source_message_end],
+            &Self::tokenize_error_reason(source),
         )
     }
 
@@ -0,0 +1,213 @@
+//! Scanner for the tokens that [erl_tokenize] doesn't support.
+//!
+//! - Triple-quoted strings and sigils (introduced in OTP 27)
+//! - `<:-`, `<:=` and `&&` symbols (introduced in OTP 28)
+use crate::items::tokens::ExtSymbol;
+use erl_tokenize::PositionRange as _;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ExtToken {
+    /// Triple-quoted string or sigil.
+    String {
+        len: usize,
+        value: String,
+    },
+    Symbol(ExtSymbol),
+}
+
+impl ExtToken {
+    /// Tries to scan a token at the beginning of `text`.
+    pub fn scan(text: &str) -> Option<Self> {
+        for symbol in [
+            ExtSymbol::StrictLeftArrow,
+            ExtSymbol::StrictDoubleLeftArrow,
+            ExtSymbol::DoubleAmpersand,
+        ] {
+            if text.starts_with(symbol.as_str()) {
+                return Some(Self::Symbol(symbol));
+            }
+        }
+
+        let (len, value) = if text.starts_with('~') {
+            scan_sigil(text)?
+        } else {
+            scan_triple_quoted_string(text)?
+        };
+        Some(Self::String { len, value })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::String { len, .. } => *len,
+            Self::Symbol(x) => x.as_str().len(),
+        }
+    }
+}
+
+/// Returns the position just after `text` that starts at `start`.
+///
+/// As [erl_tokenize::Position] cannot be constructed directly,
+/// this tokenizes a dummy string literal having the same length and line breaks as `text` instead.
+pub(crate) fn step_position(start: erl_tokenize::Position, text: &str) -> erl_tokenize::Position {
+    match text {
+        "" => return start,
+        "\n" | " " => return step_whitespace(start, text),
+        _ if text.len() == 1 => return step_whitespace(start, " "),
+        _ => {}
+    }
+    let mut dummy = vec![b'x'; text.len()];
+    for (i, b) in text.bytes().enumerate() {
+        if b == b'\n' {
+            dummy[i] = b'\n';
+        }
+    }
+    dummy[0] = b'"';
+    dummy[text.len() - 1] = b'"';
+    let dummy = String::from_utf8(dummy).expect("unreachable");
+    erl_tokenize::tokens::StringToken::from_text(&dummy, start)
+        .expect("unreachable")
+        .end_position()
+}
+
+fn step_whitespace(start: erl_tokenize::Position, text: &str) -> erl_tokenize::Position {
+    erl_tokenize::tokens::WhitespaceToken::from_text(text, start)
+        .expect("unreachable")
+        .end_position()
+}
+
+/// Returns the length of `text` up to the end of the current form
+/// (i.e., the next `.` followed by a whitespace, a comment or EOF) without tokenizing it.
+///
+/// This is used to skip a form containing a tokenize error.
+pub(crate) fn form_end(text: &str) -> usize {
+    text.char_indices()
+        .find(|&(i, c)| {
+            c == '.'
+                && text[i + 1..]
+                    .chars()
+                    .next()
+                    .is_none_or(|c| c.is_whitespace() || c == '%')
+        })
+        .map_or(text.len(), |(i, _)| i + 1)
+}
+
+/// `"""`+ ... `"""`+
+///
+/// The opening quotes must be followed by a line break and
+/// the closing ones must be placed at the beginning of a line (except for the indentation).
+fn scan_triple_quoted_string(text: &str) -> Option<(usize, String)> {
+    let quotes = text.bytes().take_while(|&b| b == b'"').count();
+    if quotes < 3 {
+        return None;
+    }
+
+    let first_line_end = quotes + text[quotes..].find('\n')?;
+    if !text[quotes..first_line_end]
+        .trim_matches([' ', '\t', '\r'])
+        .is_empty()
+    {
+        return None;
+    }
+
+    let mut lines = Vec::new();
+    let mut offset = first_line_end + 1;
+    loop {
+        let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+        let line = &text[offset..line_end];
+        let content = line.trim_start_matches([' ', '\t']);
+        if content.starts_with(&text[..quotes]) {
+            let indent = &line[..line.len() - content.len()];
+            let value = lines
+                .iter()
+                .map(|line: &&str| line.strip_prefix(indent).unwrap_or(line.trim_start()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            let len = offset + indent.len() + quotes;
+            return Some((len, value));
+        }
+        if line_end == text.len() {
+            return None;
+        }
+        lines.push(line.trim_end_matches('\r'));
+        offset = line_end + 1;
+    }
+}
+
+/// `~` `$PREFIX`? `$STRING` `$SUFFIX`?
+///
+/// - $PREFIX: `b` | `B` | `s` | `S` | ...
+/// - $STRING: triple-quoted string or a string enclosed by a pair of delimiters (e.g., `"`, `(` and `)`)
+/// - $SUFFIX: alphanumeric characters
+fn scan_sigil(text: &str) -> Option<(usize, String)> {
+    let prefix_len = text[1..]
+        .bytes()
+        .take_while(|b| b.is_ascii_alphabetic())
+        .count();
+    let prefix = &text[1..1 + prefix_len];
+    let body = &text[1 + prefix_len..];
+
+    let (body_len, value) = if let Some(x) = scan_triple_quoted_string(body) {
+        x
+    } else {
+        let open = body.chars().next()?;
+        let close = match open {
+            '(' => ')',
+            '[' => ']',
+            '{' => '}',
+            '<' => '>',
+            '/' | '|' | '\'' | '"' | '`' | '#' => open,
+            _ => return None,
+        };
+
+        // Uppercase prefixes (e.g., `~S`) denote verbatim strings.
+        let verbatim = prefix.starts_with(|c: char| c.is_ascii_uppercase());
+        let mut chars = body.char_indices().skip(1);
+        loop {
+            let (i, c) = chars.next()?;
+            if c == close {
+                break (i + 1, body[1..i].to_owned());
+            } else if c == '\\' && !verbatim {
+                chars.next();
+            }
+        }
+    };
+
+    let suffix_len = body[body_len..]
+        .bytes()
+        .take_while(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        .count();
+    Some((1 + prefix_len + body_len + suffix_len, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_works() {
+        let string = |len, value: &str| {
+            Some(ExtToken::String {
+                len,
+                value: value.to_owned(),
+            })
+        };
+
+        let text = "\"\"\"\n  foo\n\n    bar\n  \"\"\".";
+        assert_eq!(ExtToken::scan(text), string(text.len() - 1, "foo\n\n  bar"));
+        assert_eq!(ExtToken::scan("\"\"\"foo\"\"\""), None);
+        assert_eq!(ExtToken::scan("\"\"\"\nfoo\n"), None);
+
+        assert_eq!(ExtToken::scan("~\"foo\\\"\" ++"), string(8, "foo\\\""));
+        assert_eq!(ExtToken::scan("~S(foo\\)"), string(8, "foo\\"));
+        assert_eq!(ExtToken::scan("~b[a]x_1,"), string(8, "a"));
+        assert_eq!(ExtToken::scan("~B\"\"\"\n  a\n  \"\"\")"), string(15, "a"));
+        assert_eq!(ExtToken::scan("~foo"), None);
+
+        assert_eq!(
+            ExtToken::scan("<:- L"),
+            Some(ExtToken::Symbol(ExtSymbol::StrictLeftArrow))
+        );
+        assert_eq!(ExtToken::scan("<- L"), None);
+        assert_eq!(ExtToken::scan("\"foo\""), None);
+    }
+}
@@ -1,19 +1,45 @@
 use crate::items::forms::IncludeDirective;
 use crate::items::Module;
-use crate::parse::token_stream::{MacroDefine, MacroDefineKey, MacroDefines};
+use crate::parse::include_source::{FsIncludeSource, IncludeSource};
+use crate::parse::token_stream::{
+    satisfies_macro_conditions, MacroConditions, MacroDefine, MacroDefineKey, MacroDefines,
+};
 use crate::parse::TokenStream;
 use erl_tokenize::Tokenizer;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::SystemTime;
 
-const CACHE_FORMAT_VERISON: &str = "v0";
+pub(crate) const CACHE_FORMAT_VERISON: &str = "v1";
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 pub struct IncludeOptions {
     disable_include: bool,
     include_dirs: Vec<PathBuf>,
+    lib_dirs: Vec<PathBuf>,
+    otp_root: Option<PathBuf>,
+    erl_fallback: bool,
     include_cache_dir: Option<PathBuf>, // `None` means the include cache is disabled.
+    include_cache_max_size: Option<u64>,
+    include_memory_cache: Option<IncludeMemoryCache>,
+    source: Arc<dyn IncludeSource>,
+}
+
+impl Default for IncludeOptions {
+    fn default() -> Self {
+        Self {
+            disable_include: false,
+            include_dirs: Vec::new(),
+            lib_dirs: Vec::new(),
+            otp_root: None,
+            erl_fallback: false,
+            include_cache_dir: None,
+            include_cache_max_size: None,
+            include_memory_cache: None,
+            source: Arc::new(FsIncludeSource),
+        }
+    }
 }
 
 impl IncludeOptions {
@@ -31,16 +57,249 @@ impl IncludeOptions {
         self
     }
 
+    /// Sets the directories containing applications (e.g., `apps/` of an umbrella project).
+    ///
+    /// `-include_lib("APP/...")` is resolved against `DIR/APP/...` (or `DIR/APP-VSN/...`) before
+    /// the directories in `ERL_LIBS` envvar and `OTP_ROOT/lib`.
+    pub fn lib_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.lib_dirs = dirs;
+        self
+    }
+
+    /// Sets the root directory of Erlang/OTP (if omitted, it is detected from the location of `erl` command).
+    pub fn otp_root(mut self, dir: PathBuf) -> Self {
+        self.otp_root = Some(dir);
+        self
+    }
+
+    /// Resolves `-include_lib` by running `erl` command (`code:lib_dir/1`) if the application is not found otherwise.
+    pub fn erl_fallback(mut self) -> Self {
+        self.erl_fallback = true;
+        self
+    }
+
     pub fn include_cache_dir(mut self, dir: PathBuf) -> Self {
         self.include_cache_dir = Some(dir);
         self
     }
+
+    /// Limits the total size of the include cache entries (in bytes).
+    ///
+    /// If the limit is exceeded when saving a new entry, the oldest entries are removed.
+    pub fn include_cache_max_size(mut self, bytes: u64) -> Self {
+        self.include_cache_max_size = Some(bytes);
+        self
+    }
+
+    pub fn include_memory_cache(mut self, cache: IncludeMemoryCache) -> Self {
+        self.include_memory_cache = Some(cache);
+        self
+    }
+
+    /// Sets the source from which include files are read (the default is [FsIncludeSource]).
+    pub fn include_source<S: IncludeSource + 'static>(mut self, source: S) -> Self {
+        self.source = Arc::new(source);
+        self
+    }
+
+    pub(crate) fn source(&self) -> &Arc<dyn IncludeSource> {
+        &self.source
+    }
+
+    /// Resolves the path of an `-include_lib` target (e.g., `kernel/include/logger.hrl`).
+    pub(crate) fn resolve_lib_path(&self, path: &Path) -> Option<PathBuf> {
+        for dir in &self.lib_dirs {
+            let candidate_path = dir.join(path);
+            if self.source.exists(&candidate_path) {
+                return Some(candidate_path);
+            }
+        }
+
+        let std::path::Component::Normal(app_name) = path.components().next()? else {
+            return None;
+        };
+        let mut resolved_path = self.app_dir(app_name.to_str()?)?;
+        resolved_path.extend(path.components().skip(1));
+        Some(resolved_path)
+    }
+
+    /// Returns the directory of the application `app_name` (like `code:lib_dir/1`).
+    fn app_dir(&self, app_name: &str) -> Option<PathBuf> {
+        let mut lib_dirs = self.lib_dirs.clone();
+        lib_dirs.extend(crate::erl::default_lib_dirs(self.otp_root.as_deref()));
+        if let Some(dir) = crate::erl::find_lib_dir(app_name, &lib_dirs) {
+            return Some(dir);
+        }
+        if !self.erl_fallback {
+            return None;
+        }
+
+        static CACHE: OnceLock<Mutex<HashMap<String, Option<PathBuf>>>> = OnceLock::new();
+        CACHE
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(app_name.to_owned())
+            .or_insert_with(|| match crate::erl::code_lib_dir(app_name) {
+                Ok(dir) => Some(dir),
+                Err(e) => {
+                    log::warn!("{}", e);
+                    None
+                }
+            })
+            .clone()
+    }
+
+    /// Returns a string representing the options that affect the formatting results.
+    pub(crate) fn fingerprint(&self) -> String {
+        format!(
+            "{:?}",
+            (
+                self.disable_include,
+                &self.include_dirs,
+                &self.lib_dirs,
+                &self.otp_root,
+                self.erl_fallback,
+                &self.source,
+                std::env::var_os("ERL_LIBS")
+            )
+        )
+    }
+}
+
+/// An in-memory cache of the macro definitions collected during processing `-include` and `-include_lib` directives.
+///
+/// Unlike the file-based include cache, this cache is kept only while the instance (and its clones) is alive.
+/// It is useful for a long-running process that formats the same project repeatedly (e.g., a language server).
+///
+/// Like the file-based cache, the entries are keyed by the directory of the including file and the unresolved include path
+/// since the same path (e.g., `-include("types.hrl")`) can refer to different files depending on the including file.
+/// An entry is not reused if the macros checked by `-ifdef` or `-ifndef` directives
+/// in the include file are defined differently in the including file.
+#[derive(Debug, Default, Clone)]
+pub struct IncludeMemoryCache(Arc<Mutex<HashMap<CacheKey, MemoryCacheEntry>>>);
+
+/// The directory of the including file and the unresolved include path (used by both the in-memory and file-based caches).
+type CacheKey = (Option<PathBuf>, PathBuf);
+
+impl IncludeMemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(
+        &self,
+        source: &dyn IncludeSource,
+        key: &CacheKey,
+        known_macro_defines: &MacroDefines,
+    ) -> Option<IncludedMacroDefines> {
+        let mut entries = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries.get(key)?;
+        if !satisfies_macro_conditions(known_macro_defines, &entry.included.macro_conditions) {
+            log::debug!(
+                "The in-memory cache of {:?} was created under different macro definitions.",
+                entry.included.resolved_path
+            );
+            return None;
+        }
+        let modified = entry
+            .mtimes
+            .iter()
+            .find(|(path, mtime)| source.modified(path).ok() != *mtime);
+        if let Some((path, _)) = modified {
+            log::debug!(
+                "The include file {:?} has been modified or removed since the time {:?} was cached in memory.",
+                path,
+                entry.included.resolved_path
+            );
+            entries.remove(key);
+            return None;
+        }
+        Some(entry.included.clone())
+    }
+
+    fn insert(&self, source: &dyn IncludeSource, key: CacheKey, included: &IncludedMacroDefines) {
+        if included.dependencies.untracked {
+            return;
+        }
+        let mut mtimes = Vec::new();
+        for path in included.files() {
+            match source.modified(path) {
+                Ok(mtime) => mtimes.push((path.clone(), Some(mtime))),
+                Err(_) => return,
+            }
+        }
+        for path in &included.dependencies.missing_files {
+            mtimes.push((path.clone(), None));
+        }
+        let entry = MemoryCacheEntry {
+            included: included.clone(),
+            mtimes,
+        };
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, entry);
+    }
+}
+
+#[derive(Debug)]
+struct MemoryCacheEntry {
+    included: IncludedMacroDefines,
+
+    // `None` means that the file did not exist.
+    mtimes: Vec<(PathBuf, Option<SystemTime>)>,
+}
+
+/// The macro definitions collected from an include file.
+#[derive(Debug, Clone)]
+struct IncludedMacroDefines {
+    resolved_path: PathBuf,
+
+    /// The files included by the include file (directly or indirectly).
+    dependencies: IncludeDependencies,
+    macro_defines: MacroDefines,
+
+    /// The macros defined outside the include file that its `-ifdef` and `-ifndef` directives depend on.
+    macro_conditions: MacroConditions,
+}
+
+impl IncludedMacroDefines {
+    /// Returns the include file itself and the files included by it.
+    fn files(&self) -> impl '_ + Iterator<Item = &PathBuf> {
+        std::iter::once(&self.resolved_path).chain(&self.dependencies.files)
+    }
+}
+
+/// The files that the macro definitions collected by include directives depend on.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct IncludeDependencies {
+    /// The resolved include files (directly or indirectly included).
+    pub files: BTreeSet<PathBuf>,
+
+    /// The paths that were looked up but did not exist (e.g., the candidate paths of an unresolved include).
+    ///
+    /// Creating one of them can change the result of the include.
+    pub missing_files: BTreeSet<PathBuf>,
+
+    /// `true` if an include could not be resolved for a reason that is not tracked by file paths
+    /// (e.g., `-include_lib` of an unknown application).
+    pub untracked: bool,
+}
+
+impl IncludeDependencies {
+    fn extend(&mut self, other: IncludeDependencies) {
+        self.files.extend(other.files);
+        self.missing_files.extend(other.missing_files);
+        self.untracked |= other.untracked;
+    }
 }
 
 #[derive(Debug)]
 pub struct IncludeHandler {
     options: IncludeOptions,
     included: HashSet<PathBuf>,
+    dependencies: IncludeDependencies,
 }
 
 impl IncludeHandler {
@@ -48,17 +307,24 @@ impl IncludeHandler {
         Self {
             options,
             included: HashSet::new(),
+            dependencies: IncludeDependencies::default(),
         }
     }
 
-    fn cache_path(&self, include: &IncludeDirective) -> Option<PathBuf> {
+    /// Returns the files that the includes processed so far depend on.
+    pub(crate) fn dependencies(&self) -> &IncludeDependencies {
+        &self.dependencies
+    }
+
+    fn cache_path(&self, (including_dir, path): &CacheKey) -> Option<PathBuf> {
         let cache_root_dir = if let Some(dir) = &self.options.include_cache_dir {
             dir
         } else {
             return None;
         };
-        let key = if let Some(path) = include.var_substituted_path().to_str() {
-            sha256::digest(path)
+        let including_dir = including_dir.as_deref().unwrap_or(Path::new(""));
+        let key = if let (Some(dir), Some(path)) = (including_dir.to_str(), path.to_str()) {
+            sha256::digest(format!("{dir}\0{path}"))
         } else {
             return None;
         };
@@ -70,8 +336,10 @@ impl IncludeHandler {
     fn try_load_macro_defines_from_cache(
         &mut self,
         include: &IncludeDirective,
-    ) -> Option<MacroDefines> {
-        let cache_path = self.cache_path(include)?;
+        cache_key: &CacheKey,
+        known_macro_defines: &MacroDefines,
+    ) -> Option<IncludedMacroDefines> {
+        let cache_path = self.cache_path(cache_key)?;
         if !cache_path.exists() {
             log::debug!(
                 "Include cache {:?} (for {:?}) does not exist.",
@@ -107,43 +375,19 @@ impl IncludeHandler {
             }
         };
 
-        if !cache_entry.resolved_path.exists() {
-            log::warn!(
-                "Found a cache entry {:?} for a non-existing file {:?}. This entry will be deleted.",
-                cache_path,
-                cache_entry.resolved_path
-             );
+        if let Some(path) = cache_entry.find_modified_file(self.options.source.as_ref()) {
+            log::warn!("The include file {:?} has been modified or removed since the time {:?} was cached. The cache entry will be deleted.",
+                       path, cache_entry.resolved_path);
             let _ = std::fs::remove_file(&cache_path);
             return None;
         }
-
-        match std::fs::metadata(&cache_entry.resolved_path) {
-            Err(e) => {
-                log::warn!(
-                    "Failed to get the metadata of the include file {:?}: {}",
-                    cache_entry.resolved_path,
-                    e
-                );
-                return None;
-            }
-            Ok(metadata) => match metadata.modified() {
-                Err(e) => {
-                    log::warn!(
-                        "Failed to get the modified time of the include file {:?}: {}",
-                        cache_entry.resolved_path,
-                        e
-                    );
-                    return None;
-                }
-                Ok(mtime) => {
-                    if cache_entry.mtime < mtime {
-                        log::warn!("The include file {:?} has been modified since the time it was cached. The cache entry will be deleted.",
-                                   cache_entry.resolved_path);
-                        let _ = std::fs::remove_file(&cache_path);
-                        return None;
-                    }
-                }
-            },
+        if !satisfies_macro_conditions(known_macro_defines, &cache_entry.macro_conditions) {
+            log::debug!(
+                "Include cache {:?} (for {:?}) was created under different macro definitions.",
+                cache_path,
+                include.path()
+            );
+            return None;
         }
 
         let macro_defines = cache_entry
@@ -151,7 +395,20 @@ impl IncludeHandler {
             .into_iter()
             .map(|(name, define)| (MacroDefineKey::new(name, define.arity()), define))
             .collect();
-        Some(macro_defines)
+        let mut dependencies = IncludeDependencies::default();
+        for (path, hash) in cache_entry.content_hashes {
+            if hash.is_none() {
+                dependencies.missing_files.insert(path);
+            } else if path != cache_entry.resolved_path {
+                dependencies.files.insert(path);
+            }
+        }
+        Some(IncludedMacroDefines {
+            resolved_path: cache_entry.resolved_path,
+            dependencies,
+            macro_defines,
+            macro_conditions: cache_entry.macro_conditions,
+        })
     }
 
     fn include_dirs<P: AsRef<Path>>(&self, target_file_path: Option<P>) -> Vec<PathBuf> {
@@ -179,31 +436,50 @@ impl IncludeHandler {
         target_file_path: Option<P>,
         include: &IncludeDirective,
         known_macro_defines: &MacroDefines,
-    ) -> Option<MacroDefines> {
-        let resolved_path =
-            if let Some(path) = include.resolved_path(&self.include_dirs(target_file_path)) {
-                path
-            } else {
-                log::warn!(
-                    "Failed to resolve the include file path {:?}",
-                    include.path()
-                );
-                return None;
-            };
+    ) -> Option<IncludedMacroDefines> {
+        let include_dirs = self.include_dirs(target_file_path);
+        let resolved_path = if let Some(path) = include.resolved_path(&include_dirs, &self.options)
+        {
+            path
+        } else {
+            log::warn!(
+                "Failed to resolve the include file path {:?}",
+                include.path()
+            );
+            match include.candidate_paths(&include_dirs) {
+                Some(paths) => self.dependencies.missing_files.extend(paths),
+                None => self.dependencies.untracked = true,
+            }
+            return None;
+        };
         log::debug!(
             "The include file {:?} was resolved to the path {:?}",
             include.path(),
             resolved_path
         );
 
-        let text = match std::fs::read_to_string(&resolved_path) {
+        let text = match self.options.source.read_to_string(&resolved_path) {
             Ok(text) => text,
             Err(e) => {
                 log::warn!("Failed to read the include file {:?}: {}", resolved_path, e);
+                self.dependencies.missing_files.insert(resolved_path);
                 return None;
             }
         };
+        let included = self.parse_macro_defines(resolved_path.clone(), text, known_macro_defines);
+        if included.is_none() {
+            // The macro definitions may become available once the file is fixed.
+            self.dependencies.files.insert(resolved_path);
+        }
+        included
+    }
 
+    fn parse_macro_defines(
+        &self,
+        resolved_path: PathBuf,
+        text: String,
+        known_macro_defines: &MacroDefines,
+    ) -> Option<IncludedMacroDefines> {
         let mut tokenizer = Tokenizer::new(text);
         tokenizer.set_filepath(&resolved_path);
         let mut ts = TokenStream::new(tokenizer, self.options.clone());
@@ -217,17 +493,27 @@ impl IncludeHandler {
                 );
                 None
             }
-            Ok(_) => Some(ts.new_macro_defines()),
+            Ok(_) => Some(IncludedMacroDefines {
+                dependencies: {
+                    let mut dependencies = ts.include_dependencies().clone();
+                    dependencies.files.remove(&resolved_path);
+                    dependencies
+                },
+                macro_conditions: ts.external_macro_conditions().clone(),
+                macro_defines: ts.new_macro_defines(),
+                resolved_path,
+            }),
         }
     }
 
-    fn try_save_macro_defines_into_cache<P: AsRef<Path>>(
+    fn try_save_macro_defines_into_cache(
         &mut self,
-        target_file_path: Option<P>,
         include: &IncludeDirective,
-        macro_defines: &MacroDefines,
+        cache_key: &CacheKey,
+        included: &IncludedMacroDefines,
     ) {
-        let cache_path = if let Some(path) = self.cache_path(include) {
+        let resolved_path = &included.resolved_path;
+        let cache_path = if let Some(path) = self.cache_path(cache_key) {
             path
         } else {
             return;
@@ -245,36 +531,36 @@ impl IncludeHandler {
             }
         }
 
-        let resolved_path =
-            if let Some(path) = include.resolved_path(&self.include_dirs(target_file_path)) {
-                path
-            } else {
-                log::warn!(
-                    "Failed to resolve the include file path {:?}",
-                    include.path()
-                );
-                return;
-            };
-
-        let mtime = match std::fs::metadata(&resolved_path).and_then(|m| m.modified()) {
-            Err(e) => {
-                log::warn!(
-                    "Failed to get modified time of the include file {:?}: {}",
-                    resolved_path,
-                    e
-                );
-                return;
+        if included.dependencies.untracked {
+            log::debug!(
+                "Skipped saving the include cache for {:?} as it has an untracked dependency.",
+                resolved_path
+            );
+            return;
+        }
+        let mut content_hashes = Vec::new();
+        for path in included.files() {
+            match self.options.source.read_to_string(path) {
+                Err(e) => {
+                    log::debug!("Failed to read the include file {:?}: {}", path, e);
+                    return;
+                }
+                Ok(text) => content_hashes.push((path.clone(), Some(sha256::digest(text)))),
             }
-            Ok(mtime) => mtime,
-        };
+        }
+        for path in &included.dependencies.missing_files {
+            content_hashes.push((path.clone(), None));
+        }
 
         let entry = CacheEntry {
             resolved_path: resolved_path.clone(),
-            mtime,
-            macro_defines: macro_defines
+            content_hashes,
+            macro_defines: included
+                .macro_defines
                 .iter()
                 .map(|(k, v)| (k.name().to_owned(), v.clone()))
                 .collect(),
+            macro_conditions: included.macro_conditions.clone(),
         };
 
         let mut temp = match tempfile::NamedTempFile::new() {
@@ -309,19 +595,26 @@ impl IncludeHandler {
                 include.path(),
                 cache_path
             );
+            if let (Some(max_bytes), Some(dir)) =
+                (self.options.include_cache_max_size, cache_path.parent())
+            {
+                crate::cache::enforce_max_size(dir, max_bytes);
+            }
         }
     }
 
+    /// Returns the macro definitions collected from the include file
+    /// and the macro conditions that they depend on (see [IncludedMacroDefines::macro_conditions]).
     pub(crate) fn include_macro_defines<P: AsRef<Path>>(
         &mut self,
         target_file_path: Option<P>,
         include: &IncludeDirective,
         known_macro_defines: &MacroDefines,
-    ) -> MacroDefines {
+    ) -> (MacroDefines, MacroConditions) {
         if self.options.disable_include {
             log::debug!("Skipped processing an include directive for {:?} as `--directive-include` flag is set.",
                         include.path());
-            return MacroDefines::new();
+            return Default::default();
         }
 
         // To eliminate the overhead of running `erl` command to resolve the path (for `-include_lib(...)`),
@@ -332,43 +625,227 @@ impl IncludeHandler {
                 "The include of the path {:?} was skipped as it has already been included before.",
                 include.path()
             );
-            return BTreeMap::new();
+            return Default::default();
+        }
+        self.included.insert(unresolved_path.clone());
+
+        let cache_key = (
+            target_file_path
+                .as_ref()
+                .and_then(|p| p.as_ref().parent())
+                .map(Path::to_path_buf),
+            unresolved_path,
+        );
+        if let Some(included) = self
+            .options
+            .include_memory_cache
+            .as_ref()
+            .and_then(|cache| {
+                cache.get(
+                    self.options.source.as_ref(),
+                    &cache_key,
+                    known_macro_defines,
+                )
+            })
+        {
+            log::debug!(
+                "Found {} macro definitions in {:?} (cached in memory).",
+                included.macro_defines.len(),
+                include.path()
+            );
+            return self.add_included(included);
         }
-        self.included.insert(unresolved_path);
 
-        if let Some(macro_defines) = self.try_load_macro_defines_from_cache(include) {
+        if let Some(included) =
+            self.try_load_macro_defines_from_cache(include, &cache_key, known_macro_defines)
+        {
             log::debug!(
                 "Found {} macro definitions in {:?} (cached).",
-                macro_defines.len(),
+                included.macro_defines.len(),
                 include.path()
             );
 
-            return macro_defines;
+            if let Some(cache) = &self.options.include_memory_cache {
+                cache.insert(self.options.source.as_ref(), cache_key, &included);
+            }
+            return self.add_included(included);
         }
 
-        if let Some(macro_defines) =
+        if let Some(included) =
             self.try_load_macro_defines(target_file_path.as_ref(), include, known_macro_defines)
         {
             log::debug!(
                 "Found {} macro definitions in {:?}.",
-                macro_defines.len(),
+                included.macro_defines.len(),
                 include.path()
             );
 
-            self.try_save_macro_defines_into_cache(target_file_path, include, &macro_defines);
-            macro_defines
+            self.try_save_macro_defines_into_cache(include, &cache_key, &included);
+            if let Some(cache) = &self.options.include_memory_cache {
+                cache.insert(self.options.source.as_ref(), cache_key, &included);
+            }
+            self.add_included(included)
         } else {
-            BTreeMap::new()
+            Default::default()
         }
     }
+
+    fn add_included(&mut self, included: IncludedMacroDefines) -> (MacroDefines, MacroConditions) {
+        self.dependencies
+            .files
+            .insert(included.resolved_path.clone());
+        self.dependencies.extend(included.dependencies);
+        (included.macro_defines, included.macro_conditions)
+    }
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
-struct CacheEntry {
+pub(crate) struct CacheEntry {
     resolved_path: PathBuf,
-    mtime: SystemTime,
+
+    // The SHA-256 hashes of the contents of the resolved file and the files included by it (directly or indirectly).
+    // Unlike modification times, they are not affected by operations such as `git checkout`.
+    // `None` means that the file did not exist (e.g., a candidate path of an unresolved include).
+    content_hashes: Vec<(PathBuf, Option<String>)>,
 
     // Note that the restriction of `serde_json` we cannot use `MacroDefineKey`s as keys of an object.
     // So we store `MacroDefine`s as a vec and convert them into a `MacroDefines` during the loading phase.
     macro_defines: Vec<(String, MacroDefine)>,
+
+    // The macros defined outside the include file that its `-ifdef` and `-ifndef` directives depend on.
+    // The entry is not used if the including file has different definitions for them.
+    macro_conditions: MacroConditions,
+}
+
+impl CacheEntry {
+    /// Returns the first file that has been removed or modified since the entry was created.
+    fn find_modified_file(&self, source: &dyn IncludeSource) -> Option<&Path> {
+        self.content_hashes
+            .iter()
+            .find(|(path, hash)| content_hash(source, path) != *hash)
+            .map(|(path, _)| path.as_path())
+    }
+
+    /// Returns `true` if any of the included files has been removed or modified since the entry was created.
+    pub(crate) fn is_stale(&self) -> bool {
+        self.find_modified_file(&FsIncludeSource).is_some()
+    }
+}
+
+/// Returns the SHA-256 hash of the content of `path` (`None` if the file cannot be read).
+pub(crate) fn content_hash(source: &dyn IncludeSource, path: &Path) -> Option<String> {
+    source.read_to_string(path).ok().map(sha256::digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect_macro_names(file: &Path, options: IncludeOptions) -> anyhow::Result<Vec<String>> {
+        collect_macro_names_with_defines(file, options, &[])
+    }
+
+    fn collect_macro_names_with_defines(
+        file: &Path,
+        options: IncludeOptions,
+        defines: &[&str],
+    ) -> anyhow::Result<Vec<String>> {
+        let mut tokenizer = Tokenizer::new(std::fs::read_to_string(file)?);
+        tokenizer.set_filepath(file);
+        let mut ts = TokenStream::new(tokenizer, options);
+        for name in defines {
+            ts.define_macro(name, "true")?;
+        }
+        ts.parse::<Module>()?;
+        Ok(ts
+            .new_macro_defines()
+            .keys()
+            .map(|k| k.name().to_owned())
+            .collect())
+    }
+
+    #[test]
+    fn include_cache_tracks_transitive_includes() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("foo.erl");
+        let b_hrl = dir.path().join("b.hrl");
+        std::fs::write(&file, "-include(\"a.hrl\").\n")?;
+        std::fs::write(dir.path().join("a.hrl"), "-include(\"b.hrl\").\n")?;
+        std::fs::write(&b_hrl, "-define(FOO, foo).\n")?;
+
+        let options = IncludeOptions::new().include_cache_dir(dir.path().join("cache"));
+        assert_eq!(collect_macro_names(&file, options.clone())?, ["FOO"]);
+
+        // Only the indirectly included file is changed (the modification time may be the same).
+        std::fs::write(&b_hrl, "-define(BAR, bar).\n")?;
+        assert_eq!(collect_macro_names(&file, options.clone())?, ["BAR"]);
+
+        // The cached entry is used as the contents are not changed.
+        let entries = std::fs::read_dir(dir.path().join("cache").join(CACHE_FORMAT_VERISON))?;
+        assert_eq!(entries.count(), 2);
+        assert_eq!(collect_macro_names(&file, options)?, ["BAR"]);
+        Ok(())
+    }
+
+    #[test]
+    fn include_caches_distinguish_including_dirs() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut files = Vec::new();
+        for (app, name) in [("a", "FOO"), ("b", "BAR")] {
+            let src_dir = dir.path().join(app).join("src");
+            std::fs::create_dir_all(&src_dir)?;
+            std::fs::write(src_dir.join("types.hrl"), format!("-define({name}, 1).\n"))?;
+            let file = src_dir.join(format!("{app}.erl"));
+            std::fs::write(&file, "-include(\"types.hrl\").\n")?;
+            files.push(file);
+        }
+
+        for options in [
+            IncludeOptions::new().include_cache_dir(dir.path().join("cache")),
+            IncludeOptions::new().include_memory_cache(IncludeMemoryCache::new()),
+        ] {
+            assert_eq!(collect_macro_names(&files[0], options.clone())?, ["FOO"]);
+            assert_eq!(collect_macro_names(&files[1], options.clone())?, ["BAR"]);
+            assert_eq!(collect_macro_names(&files[0], options)?, ["FOO"]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn include_caches_respect_outside_macro_defines() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("foo.erl");
+        std::fs::write(&file, "-include(\"a.hrl\").\n")?;
+        std::fs::write(dir.path().join("a.hrl"), "-include(\"b.hrl\").\n")?;
+        std::fs::write(
+            dir.path().join("b.hrl"),
+            indoc::indoc! {"
+                -ifndef(B_HRL).
+                -define(B_HRL, true).
+                -ifdef(TEST).
+                -define(FOO, foo).
+                -else.
+                -define(BAR, bar).
+                -endif.
+                -endif.
+            "},
+        )?;
+
+        for options in [
+            IncludeOptions::new().include_cache_dir(dir.path().join("cache")),
+            IncludeOptions::new().include_memory_cache(IncludeMemoryCache::new()),
+        ] {
+            for _ in 0..2 {
+                assert_eq!(
+                    collect_macro_names_with_defines(&file, options.clone(), &[])?,
+                    ["BAR", "B_HRL"]
+                );
+                assert_eq!(
+                    collect_macro_names_with_defines(&file, options.clone(), &["TEST"])?,
+                    ["B_HRL", "FOO"]
+                );
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A source from which the files targeted by `-include` and `-include_lib` directives are read.
+///
+/// The default source is [FsIncludeSource].
+/// Other implementations make it possible to format code without disk access (e.g., in WebAssembly)
+/// or to see the unsaved contents of editor buffers (e.g., in a language server).
+pub trait IncludeSource: std::fmt::Debug + Send + Sync {
+    /// Returns `true` if the file exists in this source.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Reads the whole content of the file.
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String>;
+
+    /// Returns the last modification time of the file.
+    ///
+    /// If this returns an error, the macro definitions loaded from the file are not cached.
+    fn modified(&self, path: &Path) -> std::io::Result<SystemTime>;
+}
+
+/// An [IncludeSource] that reads files from the real filesystem.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FsIncludeSource;
+
+impl IncludeSource for FsIncludeSource {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+        crate::encoding::read_file(path).map(|(text, _)| text)
+    }
+
+    fn modified(&self, path: &Path) -> std::io::Result<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified())
+    }
+}
+
+/// An [IncludeSource] that holds files in memory.
+///
+/// The files not found in this source are looked up in the fallback source if specified.
+/// Note that the path of an `-include_lib` target can be registered as is (e.g., `"kernel/include/logger.hrl"`).
+#[derive(Debug, Default, Clone)]
+pub struct MemoryIncludeSource {
+    files: BTreeMap<PathBuf, String>,
+    fallback: Option<Arc<dyn IncludeSource>>,
+}
+
+impl MemoryIncludeSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file (or overwrites the existing one).
+    pub fn file<P: AsRef<Path>>(mut self, path: P, text: &str) -> Self {
+        self.insert(path, text);
+        self
+    }
+
+    /// Adds a file (or overwrites the existing one).
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, text: &str) {
+        self.files
+            .insert(path.as_ref().to_path_buf(), text.to_owned());
+    }
+
+    /// Removes a file.
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Option<String> {
+        self.files.remove(path.as_ref())
+    }
+
+    /// Sets the source used to look up the files that don't exist in memory.
+    pub fn fallback<S: IncludeSource + 'static>(mut self, source: S) -> Self {
+        self.fallback = Some(Arc::new(source));
+        self
+    }
+}
+
+impl IncludeSource for MemoryIncludeSource {
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path) || self.fallback.as_ref().is_some_and(|s| s.exists(path))
+    }
+
+    fn read_to_string(&self, path: &Path) -> std::io::Result<String> {
+        if let Some(text) = self.files.get(path) {
+            return Ok(text.clone());
+        }
+        match &self.fallback {
+            Some(source) => source.read_to_string(path),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn modified(&self, path: &Path) -> std::io::Result<SystemTime> {
+        if self.files.contains_key(path) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!("{path:?} exists only in memory"),
+            ));
+        }
+        match &self.fallback {
+            Some(source) => source.modified(path),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("{path:?} is not found"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_works() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let on_disk = dir.path().join("disk.hrl");
+        let shadowed = dir.path().join("shadowed.hrl");
+        std::fs::write(&on_disk, "-define(DISK, disk).\n")?;
+        std::fs::write(&shadowed, "-define(OPEN, {).\n")?;
+
+        let source = MemoryIncludeSource::new()
+            .file(&shadowed, "-define(OPEN, [).\n")
+            .file("memory.hrl", "-define(MEMORY, memory).\n")
+            .fallback(FsIncludeSource);
+        assert!(source.exists(&on_disk));
+        assert!(source.exists(Path::new("memory.hrl")));
+        assert!(!source.exists(&dir.path().join("missing.hrl")));
+        assert_eq!(source.read_to_string(&shadowed)?, "-define(OPEN, [).\n");
+        assert_eq!(source.read_to_string(&on_disk)?, "-define(DISK, disk).\n");
+        assert!(source.modified(&on_disk).is_ok());
+        assert!(source.modified(&shadowed).is_err());
+
+        let text = format!(
+            "-include({:?}).\n-include({:?}).\n-include(\"memory.hrl\").\n\n\nfoo() ->\n    {{?DISK, ?MEMORY, ?OPEN 1]}}.\n",
+            on_disk, shadowed
+        );
+        let formatted = crate::Options::new()
+            .include_source(source.clone())
+            .format_text::<crate::items::Module>(&text)?;
+        assert_eq!(formatted, text);
+
+        // Without the fallback, the files on disk are not visible.
+        let mut source = source;
+        source.fallback = None;
+        assert!(!source.exists(&on_disk));
+        assert!(source.read_to_string(&on_disk).is_err());
+        Ok(())
+    }
+}
@@ -1,17 +1,17 @@
-use crate::items::components::Either;
-use crate::items::forms::{DefineDirective, IncludeDirective};
+use crate::items::forms::{ConditionalDirective, DefineDirective, Directive, IncludeDirective};
 use crate::items::macros::{Macro, MacroName};
 use crate::items::symbols::{OpenParenSymbol, QuestionSymbol};
 use crate::items::tokens::{
-    AtomToken, CharToken, CommentToken, FloatToken, IntegerToken, KeywordToken, LexicalToken,
-    StringToken, SymbolToken, VariableToken,
+    AtomToken, CharToken, CommentToken, ExtSymbolToken, FloatToken, IntegerToken, KeywordToken,
+    LexicalToken, StringToken, SymbolToken, VariableToken,
 };
-use crate::parse::include::IncludeHandler;
+use crate::parse::ext_token::{form_end, step_position, ExtToken};
+use crate::parse::include::{IncludeDependencies, IncludeHandler};
 use crate::parse::{Error, IncludeOptions, Parse, Result, ResumeParse};
 use crate::span::{Position, Span};
-use erl_tokenize::values::Symbol;
+use erl_tokenize::values::{Keyword, Symbol};
 use erl_tokenize::{PositionRange as _, Tokenizer};
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 
@@ -24,16 +24,26 @@ pub struct TokenStream {
     macros: BTreeMap<Position, Macro>,
     macro_defines: MacroDefines,
     new_macro_defines: HashSet<MacroDefineKey>,
+    local_macro_names: HashSet<String>,
+    external_macro_conditions: MacroConditions,
+    conditionals: Vec<ConditionalBlock>,
     missing_macros: HashSet<String>,
     known_replacement: HashSet<(usize, Vec<LexicalToken>)>,
     disable_macro_expand: bool,
     parsing_tokens: bool,
+    error_tolerant: bool,
     text: Arc<String>,
     path: Option<Arc<PathBuf>>,
     last_parse_error: Option<Error>,
     include: IncludeHandler,
+    otp_release: Option<u32>,
+    features: HashMap<String, bool>,
 }
 
+/// Features that reserve keywords: (name, OTP release in which it is enabled by default, keywords).
+const FEATURES: &[(&str, u32, &[Keyword])] =
+    &[("maybe_expr", 27, &[Keyword::Maybe, Keyword::Else])];
+
 impl TokenStream {
     pub fn new(tokenizer: Tokenizer<String>, options: IncludeOptions) -> Self {
         let text = Arc::new(tokenizer.text().to_owned());
@@ -49,15 +59,60 @@ impl TokenStream {
             macros: BTreeMap::new(),
             macro_defines: BTreeMap::new(),
             new_macro_defines: HashSet::new(),
+            local_macro_names: HashSet::new(),
+            external_macro_conditions: BTreeMap::new(),
+            conditionals: Vec::new(),
             missing_macros: HashSet::new(),
             known_replacement: HashSet::new(),
             disable_macro_expand: false,
             parsing_tokens: false,
+            error_tolerant: false,
             text,
             path,
             last_parse_error: None,
             include: IncludeHandler::new(options),
+            otp_release: None,
+            features: HashMap::new(),
+        }
+    }
+
+    /// Makes [Module][crate::items::Module] keep unparsable forms as-is instead of returning an error.
+    pub fn set_error_tolerant(&mut self, enabled: bool) {
+        self.error_tolerant = enabled;
+    }
+
+    pub fn is_error_tolerant(&self) -> bool {
+        self.error_tolerant
+    }
+
+    /// Sets the OTP release that the target text is compiled with.
+    ///
+    /// This determines the features enabled by default (e.g., `maybe_expr` is enabled since OTP 27).
+    /// If omitted, all the features known by efmt are enabled by default.
+    pub fn set_otp_release(&mut self, release: u32) {
+        self.otp_release = Some(release);
+    }
+
+    /// Enables or disables a feature as if `-feature($NAME, enable|disable).` is placed at the beginning of the text.
+    pub fn set_feature(&mut self, name: &str, enabled: bool) {
+        self.features.insert(name.to_owned(), enabled);
+    }
+
+    pub fn is_feature_enabled(&self, name: &str) -> bool {
+        if let Some(enabled) = self.features.get(name) {
+            return *enabled;
         }
+        FEATURES
+            .iter()
+            .find(|(feature, _, _)| *feature == name)
+            .is_some_and(|(_, release, _)| self.otp_release.is_none_or(|x| x >= *release))
+    }
+
+    fn is_keyword_enabled(&self, keyword: Keyword) -> bool {
+        FEATURES
+            .iter()
+            .filter(|(_, _, keywords)| keywords.contains(&keyword))
+            .all(|(feature, _, _)| self.is_feature_enabled(feature))
     }
 
     pub(crate) fn set_known_macro_defines(&mut self, macro_defines: MacroDefines) {
@@ -65,6 +120,34 @@ impl TokenStream {
         self.macro_defines = macro_defines;
     }
 
+    /// Defines a macro as if `-define($NAME, $VALUE).` is placed at the beginning of the text.
+    pub fn define_macro(&mut self, name: &str, value: &str) -> Result<()> {
+        let text = format!("-define({name}, {value}).");
+        let mut ts = Self::new(
+            Tokenizer::new(text),
+            IncludeOptions::new().disable_include(),
+        );
+        let define: DefineDirective = ts.parse()?;
+        let name = define.macro_name().to_owned();
+        let define = MacroDefine::from(define);
+        let key = MacroDefineKey::new(name, define.arity());
+        self.macro_defines.insert(key, define);
+        Ok(())
+    }
+
+    /// Returns the files that the `-include` or `-include_lib` directives processed so far depend on.
+    pub(crate) fn include_dependencies(&self) -> &IncludeDependencies {
+        self.include.dependencies()
+    }
+
+    /// Returns the macros checked by `-ifdef` or `-ifndef` directives (including the ones in the included files)
+    /// that were neither defined nor undefined in the text, with whether they were defined at the time.
+    ///
+    /// The results of the processing depend on the macro definitions given from outside only through these.
+    pub(crate) fn external_macro_conditions(&self) -> &MacroConditions {
+        &self.external_macro_conditions
+    }
+
     pub(crate) fn new_macro_defines(self) -> MacroDefines {
         self.macro_defines
             .iter()
@@ -103,7 +186,7 @@ impl TokenStream {
             if self
                 .last_parse_error
                 .as_ref()
-                .map_or(true, |e0| e0.position() < e.position())
+                .is_none_or(|e0| e0.position() < e.position())
             {
                 self.last_parse_error = Some(e.clone());
             }
@@ -112,7 +195,7 @@ impl TokenStream {
             if self
                 .last_parse_error
                 .as_ref()
-                .map_or(false, |e| e.position() < succeeded_position)
+                .is_some_and(|e| e.position() < succeeded_position)
             {
                 self.last_parse_error = None;
             }
@@ -120,6 +203,23 @@ impl TokenStream {
         result
     }
 
+    /// Skips the text until the end of the current form without tokenizing it.
+    ///
+    /// This is used to recover from a tokenize error in the error-tolerant mode and returns the skipped region.
+    pub(crate) fn skip_untokenizable_form(&mut self) -> (Position, Position) {
+        let start = match self.tokens.get(self.current_token_index) {
+            Some(token) => token.start_position(),
+            None => self.tokenizer.next_position().into(),
+        };
+        self.tokens.truncate(self.current_token_index);
+
+        let from = self.tokenizer.next_position();
+        let rest = &self.text[from.offset()..];
+        let end = step_position(from, &rest[..form_end(rest)]);
+        self.tokenizer.set_position(end.clone());
+        (start, end.into())
+    }
+
     pub fn take_last_error(&mut self) -> Option<Error> {
         self.last_parse_error.take()
     }
@@ -221,12 +321,21 @@ impl TokenStream {
             return Ok(None);
         }
 
-        while let Some(token) = self
-            .tokenizer
-            .next()
-            .transpose()
-            .map_err(|e| Error::tokenize_error(self, e))?
-        {
+        loop {
+            if let Some(token) = self.read_ext_token() {
+                self.tokens.push(token.clone());
+                self.current_token_index += 1;
+                return Ok(Some(token));
+            }
+
+            let Some(token) = self
+                .tokenizer
+                .next()
+                .transpose()
+                .map_err(|e| Error::tokenize_error(self, e))?
+            else {
+                break;
+            };
             let start_position = Position::from(token.start_position());
             let end_position = Position::from(token.end_position());
             let token: LexicalToken = match token {
@@ -234,9 +343,10 @@ impl TokenStream {
                     continue;
                 }
                 erl_tokenize::Token::Comment(x) => {
-                    let is_trailing = self.tokens.last().map_or(false, |y| {
-                        y.start_position().line() == x.start_position().line()
-                    });
+                    let is_trailing = self
+                        .tokens
+                        .last()
+                        .is_some_and(|y| y.start_position().line() == x.start_position().line());
                     self.comments.insert(
                         start_position,
                         CommentToken::new(is_trailing, start_position, end_position),
@@ -270,12 +380,15 @@ impl TokenStream {
             self.current_token_index += 1;
 
             match &token {
-                LexicalToken::Symbol(x) if x.value() == Symbol::Question => {
-                    if !self.disable_macro_expand {
-                        return self.expand_macro_and_read_token();
-                    }
+                LexicalToken::Symbol(x)
+                    if x.value() == Symbol::Question && !self.disable_macro_expand =>
+                {
+                    return self.expand_macro_and_read_token();
                 }
-                LexicalToken::Symbol(x) if x.value() == Symbol::Hyphen => {
+                // Directives only appear at the start of a form.
+                LexicalToken::Symbol(x)
+                    if x.value() == Symbol::Hyphen && self.is_at_form_start() =>
+                {
                     let index = self.current_token_index;
                     self.try_handle_directives()?;
                     self.current_token_index = index;
@@ -289,6 +402,21 @@ impl TokenStream {
         Ok(None)
     }
 
+    /// Reads a token that [erl_tokenize] doesn't support (e.g., a triple-quoted string).
+    fn read_ext_token(&mut self) -> Option<LexicalToken> {
+        let start = self.tokenizer.next_position();
+        let text = &self.text[start.offset()..];
+        let token = ExtToken::scan(text)?;
+        let end = step_position(start.clone(), &text[..token.len()]);
+        self.tokenizer.set_position(end.clone());
+
+        let (start, end) = (Position::from(start), Position::from(end));
+        Some(match token {
+            ExtToken::String { value, .. } => StringToken::new(&value, start, end).into(),
+            ExtToken::Symbol(x) => ExtSymbolToken::new(x, start, end).into(),
+        })
+    }
+
     fn is_macro_defined(&self, name: &str) -> (bool, bool) {
         let key = MacroDefineKey::new(name.to_owned(), None);
         let mut without_args = false;
@@ -433,42 +561,147 @@ impl TokenStream {
         self.current_token_index = start_index;
     }
 
+    fn is_at_form_start(&self) -> bool {
+        let prev = self.tokens.len().checked_sub(2).map(|i| &self.tokens[i]);
+        match prev {
+            None => true,
+            Some(LexicalToken::Symbol(x)) => x.value() == Symbol::Dot,
+            Some(_) => false,
+        }
+    }
+
     fn try_handle_directives(&mut self) -> Result<()> {
         self.current_token_index -= 1;
-        let result: Result<Either<DefineDirective, IncludeDirective>> = self.parse();
+        let result: Result<Directive> = self.parse();
         match result {
-            Ok(Either::A(x)) => {
+            Ok(Directive::Define(x)) => {
                 let name = x.macro_name().to_owned();
-                let define: MacroDefine = x.into();
+                let define: MacroDefine = (*x).into();
                 let key = MacroDefineKey::new(name, define.arity());
+                self.local_macro_names.insert(key.name.clone());
                 self.new_macro_defines.insert(key.clone());
                 self.macro_defines.insert(key, define);
             }
-            Ok(Either::B(x)) => {
+            Ok(Directive::Include(x)) => {
                 self.handle_include(x);
             }
+            Ok(Directive::Undef(x)) => {
+                let name = x.macro_name();
+                self.local_macro_names.insert(name.to_owned());
+                self.macro_defines.retain(|k, _| k.name != name);
+            }
+            Ok(Directive::Conditional(ConditionalDirective::Ifdef(x))) => {
+                let name = x.macro_name();
+                let (without_args, with_args) = self.is_macro_defined(name);
+                let is_predefined = get_predefined_macro(name, x.start_position()).is_some();
+                let defined = without_args || with_args || is_predefined;
+                if !is_predefined && !self.local_macro_names.contains(name) {
+                    self.external_macro_conditions
+                        .entry(name.to_owned())
+                        .or_insert(defined);
+                }
+                self.enter_conditional(defined != x.is_ifndef());
+            }
+            // Conditions that cannot be evaluated statically are regarded as `true`.
+            Ok(Directive::Conditional(ConditionalDirective::If(x))) => {
+                self.enter_conditional(x.condition().unwrap_or(true));
+            }
+            Ok(Directive::Conditional(ConditionalDirective::Elif(x))) => {
+                self.switch_conditional_branch(x.condition().unwrap_or(true));
+            }
+            Ok(Directive::Conditional(ConditionalDirective::Else(_))) => {
+                self.switch_conditional_branch(true);
+            }
+            Ok(Directive::Conditional(ConditionalDirective::Endif(_))) => {
+                self.exit_conditional();
+            }
+            Ok(Directive::Feature(x)) => {
+                self.set_feature(x.name(), x.is_enabled());
+            }
             Err(_) => {}
         }
         Ok(())
     }
 
+    fn enter_conditional(&mut self, taken: bool) {
+        self.conditionals.push(ConditionalBlock {
+            entry_macro_defines: self.macro_defines.clone(),
+            taken_macro_defines: None,
+            is_current_branch_taken: taken,
+        });
+    }
+
+    fn switch_conditional_branch(&mut self, taken: bool) {
+        let Some(block) = self.conditionals.last_mut() else {
+            log::debug!("Found an `-else` or `-elif` directive without the corresponding `-if`.");
+            return;
+        };
+        if block.is_current_branch_taken {
+            block.taken_macro_defines = Some(std::mem::replace(
+                &mut self.macro_defines,
+                block.entry_macro_defines.clone(),
+            ));
+        } else {
+            self.macro_defines = block.entry_macro_defines.clone();
+        }
+        block.is_current_branch_taken = taken && block.taken_macro_defines.is_none();
+    }
+
+    fn exit_conditional(&mut self) {
+        let Some(block) = self.conditionals.pop() else {
+            log::debug!("Found an `-endif` directive without the corresponding `-if`.");
+            return;
+        };
+        if !block.is_current_branch_taken {
+            self.macro_defines = block
+                .taken_macro_defines
+                .unwrap_or(block.entry_macro_defines);
+        }
+    }
+
     fn handle_include(&mut self, include: IncludeDirective) {
-        let new_macro_defines = self.include.include_macro_defines(
+        let (new_macro_defines, macro_conditions) = self.include.include_macro_defines(
             self.filepath().as_deref(),
             &include,
             &self.macro_defines,
         );
+        for (name, defined) in macro_conditions {
+            if !self.local_macro_names.contains(&name) {
+                self.external_macro_conditions
+                    .entry(name)
+                    .or_insert(defined);
+            }
+        }
+        self.local_macro_names
+            .extend(new_macro_defines.keys().map(|k| k.name.clone()));
         self.new_macro_defines
             .extend(new_macro_defines.keys().cloned());
         self.macro_defines.extend(new_macro_defines);
     }
 }
 
+/// The state of an `-if`, `-ifdef` or `-ifndef` block being processed.
+///
+/// Each branch of the block is parsed with the macro definitions visible at the beginning of the block,
+/// and the definitions made in the (statically) taken branch are kept after the block.
+#[derive(Debug)]
+struct ConditionalBlock {
+    entry_macro_defines: MacroDefines,
+    taken_macro_defines: Option<MacroDefines>,
+    is_current_branch_taken: bool,
+}
+
 impl Iterator for TokenStream {
     type Item = Result<LexicalToken>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.read_token().transpose()
+        match self.read_token() {
+            // Keywords reserved by disabled features (e.g., `maybe`) are ordinary atoms.
+            Ok(Some(LexicalToken::Keyword(x))) if !self.is_keyword_enabled(x.value()) => Some(Ok(
+                AtomToken::new(x.value().as_str(), x.start_position(), x.end_position()).into(),
+            )),
+            result => result.transpose(),
+        }
     }
 }
 
@@ -546,3 +779,16 @@ impl MacroDefine {
 }
 
 pub(crate) type MacroDefines = BTreeMap<MacroDefineKey, MacroDefine>;
+
+/// Macro names and whether they are defined (see [TokenStream::external_macro_conditions]).
+pub(crate) type MacroConditions = BTreeMap<String, bool>;
+
+/// Returns `true` if `macro_defines` satisfies all of `conditions`.
+pub(crate) fn satisfies_macro_conditions(
+    macro_defines: &MacroDefines,
+    conditions: &MacroConditions,
+) -> bool {
+    conditions
+        .iter()
+        .all(|(name, defined)| macro_defines.keys().any(|k| k.name() == name) == *defined)
+}
@@ -0,0 +1,275 @@
+//! Range formatting.
+use crate::span::{Position, Span};
+use std::ops::Range;
+
+/// A range of an input text to be formatted.
+///
+/// The range is expanded to the enclosing top-level forms before formatting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatRange {
+    /// Lines from `start` to `end` (1-origin and both inclusive).
+    Lines { start: usize, end: usize },
+
+    /// Text between the two positions.
+    Positions { start: Position, end: Position },
+}
+
+impl FormatRange {
+    fn to_offsets(self, text: &str) -> (usize, usize) {
+        match self {
+            Self::Lines { start, end } => {
+                let start = line_start_offset(text, start);
+                let end = line_start_offset(text, end.saturating_add(1)).saturating_sub(1);
+                (start, std::cmp::max(start, end))
+            }
+            Self::Positions { start, end } => (start.offset(), end.offset()),
+        }
+    }
+}
+
+impl From<std::ops::RangeInclusive<usize>> for FormatRange {
+    fn from(x: std::ops::RangeInclusive<usize>) -> Self {
+        Self::Lines {
+            start: *x.start(),
+            end: *x.end(),
+        }
+    }
+}
+
+impl From<Range<Position>> for FormatRange {
+    fn from(x: Range<Position>) -> Self {
+        Self::Positions {
+            start: x.start,
+            end: x.end,
+        }
+    }
+}
+
+impl std::str::FromStr for FormatRange {
+    type Err = anyhow::Error;
+
+    /// Parses a line range in the form of `START:END`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("expected `START:END`, but got {s:?}"))?;
+        let start: usize = start.parse()?;
+        let end: usize = end.parse()?;
+        anyhow::ensure!(
+            0 < start && start <= end,
+            "invalid line range {s:?} (lines are 1-origin and START must not be greater than END)"
+        );
+        Ok(Self::Lines { start, end })
+    }
+}
+
+/// An edit that replaces a region of the original text with the formatted one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    start: Position,
+    end: Position,
+    new_text: String,
+}
+
+impl TextEdit {
+    pub fn new(start: Position, end: Position, new_text: String) -> Self {
+        Self {
+            start,
+            end,
+            new_text,
+        }
+    }
+
+    pub fn new_text(&self) -> &str {
+        &self.new_text
+    }
+}
+
+impl Span for TextEdit {
+    fn start_position(&self) -> Position {
+        self.start
+    }
+
+    fn end_position(&self) -> Position {
+        self.end
+    }
+}
+
+/// Applies `edits` to `text`.
+///
+/// Note that `edits` must be sorted by their positions and must not overlap each other.
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut offset = 0;
+    for edit in edits {
+        result.push_str(&text[offset..edit.start.offset()]);
+        result.push_str(&edit.new_text);
+        offset = edit.end.offset();
+    }
+    result.push_str(&text[offset..]);
+    result
+}
+
+/// Returns the sorted and non-overlapping byte ranges of the lines covered by
+/// the top-level forms that intersect with `ranges`.
+pub(crate) fn resolve_regions<T: Span>(
+    text: &str,
+    forms: &[T],
+    ranges: &[FormatRange],
+) -> Vec<Range<usize>> {
+    let overlaps = |form: &T, start: usize, end: usize| {
+        form.start_position().offset() <= end && start <= form.end_position().offset()
+    };
+
+    let mut regions = Vec::new();
+    for range in ranges {
+        let (start, end) = range.to_offsets(text);
+        let mut region = if let Some((first, last)) = forms
+            .iter()
+            .position(|f| overlaps(f, start, end))
+            .zip(forms.iter().rposition(|f| overlaps(f, start, end)))
+        {
+            line_range(text, &forms[first]).start..line_range(text, &forms[last]).end
+        } else {
+            continue;
+        };
+
+        // Other forms may share the first or the last line of the region.
+        loop {
+            let mut expanded = region.clone();
+            for form in forms
+                .iter()
+                .filter(|f| overlaps(f, region.start, region.end.saturating_sub(1)))
+            {
+                let lines = line_range(text, form);
+                expanded.start = std::cmp::min(expanded.start, lines.start);
+                expanded.end = std::cmp::max(expanded.end, lines.end);
+            }
+            if expanded == region {
+                break;
+            }
+            region = expanded;
+        }
+        regions.push(region);
+    }
+
+    regions.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for region in regions {
+        match merged.last_mut() {
+            Some(last) if region.start <= last.end => {
+                last.end = std::cmp::max(last.end, region.end);
+            }
+            _ => merged.push(region),
+        }
+    }
+    merged
+}
+
+/// Returns the [Position] corresponding to the given byte offset of `text`.
+pub(crate) fn position_at(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = offset - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    Position::new(offset, line, column)
+}
+
+fn line_range(text: &str, span: &impl Span) -> Range<usize> {
+    let start = span.start_position().offset();
+    let end = span.end_position().offset();
+    let start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+    start..end
+}
+
+fn line_start_offset(text: &str, line: usize) -> usize {
+    if line <= 1 {
+        return 0;
+    }
+    text.match_indices('\n')
+        .nth(line - 2)
+        .map_or(text.len(), |(i, _)| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format_range(text: &str, range: FormatRange) -> String {
+        let edits = crate::Options::new()
+            .max_columns(20)
+            .format_text_ranges(text, &[range])
+            .unwrap();
+        apply_edits(text, &edits)
+    }
+
+    #[test]
+    fn format_lines_works() {
+        let text = indoc::indoc! {"
+        -module(foo).
+        %% comment
+        foo()->foo.
+
+        bar()->   % trailing
+           bar.
+        baz()->baz.
+        "};
+        similar_asserts::assert_str_eq!(
+            format_range(text, (5..=5).into()),
+            indoc::indoc! {"
+            -module(foo).
+            %% comment
+            foo()->foo.
+
+            bar() ->  % trailing
+                bar.
+            baz()->baz.
+            "}
+        );
+        similar_asserts::assert_str_eq!(
+            format_range(text, (3..=3).into()),
+            indoc::indoc! {"
+            -module(foo).
+            %% comment
+            foo() ->
+                foo.
+
+            bar()->   % trailing
+               bar.
+            baz()->baz.
+            "}
+        );
+        similar_asserts::assert_str_eq!(format_range(text, (4..=4).into()), text);
+    }
+
+    #[test]
+    fn format_bom_crlf_lines_works() {
+        let text = "\u{feff}foo()->foo.\r\nbar()->bar.\r\n";
+        similar_asserts::assert_str_eq!(
+            format_range(text, (1..=1).into()),
+            "\u{feff}foo() ->\r\n    foo.\r\nbar()->bar.\r\n"
+        );
+        similar_asserts::assert_str_eq!(
+            format_range(text, (2..=2).into()),
+            "\u{feff}foo()->foo.\r\nbar() ->\r\n    bar.\r\n"
+        );
+    }
+
+    #[test]
+    fn format_forms_sharing_a_line_works() {
+        let text = "foo()->foo. bar()->bar.\nbaz()->baz.\n";
+        let start = Position::new(12, 1, 13);
+        similar_asserts::assert_str_eq!(
+            format_range(text, (start..start).into()),
+            indoc::indoc! {"
+            foo() ->
+                foo.
+
+
+            bar() ->
+                bar.
+            baz()->baz.
+            "}
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Machine-readable reports of the check results.
+use crate::diff::Hunk;
+use crate::span::Position;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Output format of a check report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// JSON.
+    Json,
+
+    /// Checkstyle XML.
+    Checkstyle,
+
+    /// SARIF (Static Analysis Results Interchange Format) v2.1.0.
+    Sarif,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "checkstyle" => Ok(Self::Checkstyle),
+            "sarif" => Ok(Self::Sarif),
+            _ => anyhow::bail!(
+                "unknown report format {s:?} (expected one of `json`, `checkstyle` or `sarif`)"
+            ),
+        }
+    }
+}
+
+/// Check status of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    /// The file is already formatted correctly.
+    Formatted,
+
+    /// The file needs to be formatted.
+    Unformatted,
+
+    /// The file could not be formatted.
+    Error,
+}
+
+/// An error occurred during checking a file.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ErrorReport {
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl ErrorReport {
+    /// Makes an [ErrorReport] instance.
+    ///
+    /// If `error` is caused by a [crate::Error], its reason and position are used.
+    pub fn new(error: &anyhow::Error) -> Self {
+        let efmt_error = error.chain().find_map(|e| e.downcast_ref::<crate::Error>());
+        match efmt_error.and_then(|e| Some((e, e.position()?))) {
+            Some((e, position)) => Self::with_position(e.reason(), position),
+            None => Self {
+                message: efmt_error.map_or_else(|| error.to_string(), |e| e.to_string()),
+                line: None,
+                column: None,
+            },
+        }
+    }
+
+    fn with_position(message: String, position: Position) -> Self {
+        Self {
+            message,
+            line: Some(position.line()),
+            column: Some(position.column()),
+        }
+    }
+}
+
+/// Check result of a file.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub status: FileStatus,
+    pub hunks: Vec<Hunk>,
+    pub errors: Vec<ErrorReport>,
+}
+
+impl FileReport {
+    /// Makes a report of a file by comparing the original text and the formatted one.
+    pub fn new<P: AsRef<Path>>(path: P, original: &str, formatted: &str) -> Self {
+        let hunks = crate::diff::text_hunks(original, formatted);
+        let status = if hunks.is_empty() {
+            FileStatus::Formatted
+        } else {
+            FileStatus::Unformatted
+        };
+        Self {
+            path: path.as_ref().to_path_buf(),
+            status,
+            hunks,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Makes a report of a file that could not be formatted.
+    pub fn error<P: AsRef<Path>>(path: P, error: &anyhow::Error) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            status: FileStatus::Error,
+            hunks: Vec::new(),
+            errors: vec![ErrorReport::new(error)],
+        }
+    }
+
+    fn path_string(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+}
+
+/// Writes `reports` to `writer` in the specified format.
+pub fn write_report<W: Write>(
+    mut writer: W,
+    format: ReportFormat,
+    reports: &[FileReport],
+) -> anyhow::Result<()> {
+    match format {
+        ReportFormat::Json => {
+            #[derive(serde::Serialize)]
+            struct JsonReport<'a> {
+                files: &'a [FileReport],
+            }
+            serde_json::to_writer_pretty(&mut writer, &JsonReport { files: reports })?;
+            writeln!(writer)?;
+        }
+        ReportFormat::Checkstyle => write_checkstyle(writer, reports)?,
+        ReportFormat::Sarif => {
+            serde_json::to_writer_pretty(&mut writer, &sarif(reports))?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+const UNFORMATTED_MESSAGE: &str = "The code is not formatted correctly.";
+
+fn write_checkstyle<W: Write>(mut writer: W, reports: &[FileReport]) -> std::io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(writer, r#"<checkstyle version="4.3">"#)?;
+    for report in reports {
+        writeln!(
+            writer,
+            r#"  <file name="{}">"#,
+            escape_xml(&report.path_string())
+        )?;
+        for hunk in &report.hunks {
+            writeln!(
+                writer,
+                r#"    <error line="{}" severity="warning" message="{}" source="efmt"/>"#,
+                hunk.original_start_line,
+                escape_xml(&format!("{UNFORMATTED_MESSAGE}\n{}", hunk.diff))
+            )?;
+        }
+        for error in &report.errors {
+            write!(writer, r#"    <error line="{}""#, error.line.unwrap_or(1))?;
+            if let Some(column) = error.column {
+                write!(writer, r#" column="{column}""#)?;
+            }
+            writeln!(
+                writer,
+                r#" severity="error" message="{}" source="efmt"/>"#,
+                escape_xml(&error.message)
+            )?;
+        }
+        writeln!(writer, "  </file>")?;
+    }
+    writeln!(writer, "</checkstyle>")?;
+    Ok(())
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\n' => escaped.push_str("&#10;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn sarif(reports: &[FileReport]) -> serde_json::Value {
+    let location = |report: &FileReport, region: serde_json::Value| {
+        serde_json::json!([{
+            "physicalLocation": {
+                "artifactLocation": { "uri": report.path_string().replace('\\', "/") },
+                "region": region
+            }
+        }])
+    };
+
+    let mut results = Vec::new();
+    for report in reports {
+        for hunk in &report.hunks {
+            let end_line = hunk.original_start_line + hunk.original_line_count.saturating_sub(1);
+            results.push(serde_json::json!({
+                "ruleId": "unformatted",
+                "level": "warning",
+                "message": { "text": format!("{UNFORMATTED_MESSAGE}\n{}", hunk.diff) },
+                "locations": location(report, serde_json::json!({
+                    "startLine": hunk.original_start_line,
+                    "endLine": end_line
+                }))
+            }));
+        }
+        for error in &report.errors {
+            let mut region = serde_json::json!({ "startLine": error.line.unwrap_or(1) });
+            if let Some(column) = error.column {
+                region["startColumn"] = column.into();
+            }
+            results.push(serde_json::json!({
+                "ruleId": "error",
+                "level": "error",
+                "message": { "text": error.message },
+                "locations": location(report, region)
+            }));
+        }
+    }
+
+    serde_json::json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "efmt",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_HOMEPAGE"),
+                    "rules": [
+                        {
+                            "id": "unformatted",
+                            "shortDescription": { "text": "The code is not formatted correctly." }
+                        },
+                        {
+                            "id": "error",
+                            "shortDescription": { "text": "The code could not be formatted." }
+                        }
+                    ]
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_report_works() {
+        let original = "foo()->foo.\nbar() ->\n    bar.\n";
+        let formatted = "foo() ->\n    foo.\nbar() ->\n    bar.\n";
+        let report = FileReport::new("foo.erl", original, formatted);
+        assert_eq!(report.status, FileStatus::Unformatted);
+        assert_eq!(report.hunks.len(), 1);
+        assert_eq!(report.hunks[0].original_start_line, 1);
+        assert_eq!(report.hunks[0].original_line_count, 1);
+        assert_eq!(report.hunks[0].formatted_start_line, 1);
+        assert_eq!(report.hunks[0].formatted_line_count, 2);
+
+        let error = crate::Options::new()
+            .format_text::<crate::items::Module>("foo() -> .")
+            .unwrap_err();
+        let report = FileReport::error("bar.erl", &error.into());
+        assert_eq!(report.status, FileStatus::Error);
+        assert_eq!(report.errors[0].message, "unexpected token");
+        assert_eq!(report.errors[0].line, Some(1));
+        assert_eq!(report.errors[0].column, Some(10));
+
+        let mut buf = Vec::new();
+        write_report(&mut buf, ReportFormat::Json, &[report]).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json["files"][0]["status"], "error");
+        assert_eq!(json["files"][0]["errors"][0]["column"], 10);
+    }
+
+    #[test]
+    fn checkstyle_report_works() {
+        let report = FileReport::new("<foo>.erl", "foo()->foo.\n", "foo() ->\n    foo.\n");
+        let mut buf = Vec::new();
+        write_report(&mut buf, ReportFormat::Checkstyle, &[report]).unwrap();
+        let xml = String::from_utf8(buf).unwrap();
+        assert!(xml.contains(r#"<file name="&lt;foo&gt;.erl">"#));
+        assert!(xml.contains(r#"<error line="1" severity="warning""#));
+    }
+
+    #[test]
+    fn sarif_report_works() {
+        let unformatted = FileReport::new("src\\foo.erl", "foo()->foo.\n", "foo() ->\n    foo.\n");
+        let error = crate::Options::new()
+            .format_text::<crate::items::Module>("foo() -> .")
+            .unwrap_err();
+        let error = FileReport::error("bar.erl", &error.into());
+        let mut buf = Vec::new();
+        write_report(&mut buf, ReportFormat::Sarif, &[unformatted, error]).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(json["version"], "2.1.0");
+        let rules = &json["runs"][0]["tool"]["driver"]["rules"];
+        assert_eq!(rules[0]["id"], "unformatted");
+        assert_eq!(rules[1]["id"], "error");
+
+        let results = &json["runs"][0]["results"];
+        assert_eq!(results.as_array().map(Vec::len), Some(2));
+        assert_eq!(results[0]["ruleId"], "unformatted");
+        assert_eq!(results[0]["level"], "warning");
+        let location = &results[0]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/foo.erl");
+        assert_eq!(location["region"]["startLine"], 1);
+        assert_eq!(location["region"]["endLine"], 1);
+
+        assert_eq!(results[1]["ruleId"], "error");
+        assert_eq!(results[1]["level"], "error");
+        assert_eq!(results[1]["message"]["text"], "unexpected token");
+        let location = &results[1]["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "bar.erl");
+        assert_eq!(location["region"]["startLine"], 1);
+        assert_eq!(location["region"]["startColumn"], 10);
+    }
+}
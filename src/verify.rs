@@ -0,0 +1,205 @@
+//! Safeguards to detect formatting bugs.
+use crate::error::{generate_error_message, Error};
+use crate::parse::ext_token::{form_end, step_position, ExtToken};
+use crate::span::Position;
+use erl_tokenize::{PositionRange as _, Token, Tokenizer};
+use std::path::Path;
+
+const FORMATTED: &str = "<formatted>";
+
+/// Checks that `formatted` consists of the same tokens (except for whitespaces) as `original`.
+pub(crate) fn verify_tokens(
+    path: Option<&Path>,
+    original: &str,
+    formatted: &str,
+) -> std::result::Result<(), Error> {
+    let mismatch = |original_position: Option<Position>,
+                    formatted_position: Option<Position>,
+                    message: String| Error::TokenMismatch {
+        path: path.map(|p| p.to_path_buf()),
+        original_position,
+        formatted_position,
+        message,
+    };
+
+    let tokens0 = VisibleTokens::new(original);
+    let mut tokens1 = VisibleTokens::new(formatted);
+    for t0 in tokens0 {
+        let (p0, t0) = match t0 {
+            Ok(t0) => t0,
+            Err((e, skipped0)) => {
+                // A form that could not be tokenized is kept as-is only in the error-tolerant mode.
+                if matches!(tokens1.next(), Some(Err((_, skipped1))) if skipped0 == skipped1) {
+                    continue;
+                }
+                return Err(Error::Tokenize(crate::parse::Error::TokenizeError {
+                    source: e,
+                    text: std::sync::Arc::new(original.to_owned()),
+                }));
+            }
+        };
+        let (p1, t1) = match tokens1.next() {
+            Some(Ok(t1)) => t1,
+            Some(Err((e, _))) => {
+                let reason = e.to_string();
+                let reason_end = reason.find(" (").unwrap_or(reason.len());
+                let p1 = e.position().clone().into();
+                return Err(mismatch(
+                    Some(p0),
+                    Some(p1),
+                    generate_error_message(formatted, Some(FORMATTED), p1, &reason[..reason_end]),
+                ));
+            }
+            None => {
+                return Err(mismatch(
+                    Some(p0),
+                    None,
+                    generate_error_message(original, path, p0, "extra token"),
+                ));
+            }
+        };
+        if t0 != t1 {
+            return Err(mismatch(
+                Some(p0),
+                Some(p1),
+                format!(
+                    "{}\n{}",
+                    generate_error_message(original, path, p0, "expected"),
+                    generate_error_message(formatted, Some(FORMATTED), p1, "actual"),
+                ),
+            ));
+        }
+    }
+    if let Some(t1) = tokens1.next() {
+        let p1 = t1.map_or_else(|(e, _)| e.position().clone().into(), |(p, _)| p);
+        return Err(mismatch(
+            None,
+            Some(p1),
+            generate_error_message(formatted, Some(FORMATTED), p1, "extra token"),
+        ));
+    }
+    Ok(())
+}
+
+/// Iterator over the texts of non-whitespace tokens (including the ones that [Tokenizer] doesn't support).
+///
+/// If a tokenize error occurs, the rest of the form is skipped and returned with the error.
+struct VisibleTokens<'a> {
+    text: &'a str,
+    tokenizer: Tokenizer<&'a str>,
+}
+
+impl<'a> VisibleTokens<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            tokenizer: Tokenizer::new(text),
+        }
+    }
+}
+
+impl<'a> Iterator for VisibleTokens<'a> {
+    type Item = std::result::Result<(Position, &'a str), (erl_tokenize::Error, &'a str)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = self.tokenizer.next_position();
+            let rest = &self.text[start.offset()..];
+            if let Some(token) = ExtToken::scan(rest) {
+                let text = &rest[..token.len()];
+                self.tokenizer
+                    .set_position(step_position(start.clone(), text));
+                return Some(Ok((start.into(), text)));
+            }
+
+            let token = match self.tokenizer.next()? {
+                Ok(token) => token,
+                Err(e) => {
+                    let skipped = &rest[..form_end(rest)];
+                    self.tokenizer.set_position(step_position(start, skipped));
+                    return Some(Err((e, skipped)));
+                }
+            };
+            let text = &self.text[start.offset()..token.end_position().offset()];
+            match token {
+                Token::Whitespace(_) => {}
+                Token::Comment(_) => return Some(Ok((start.into(), text.trim_end()))),
+                _ => return Some(Ok((start.into(), text))),
+            }
+        }
+    }
+}
+
+/// Checks that formatting `formatted` again yields the same text.
+pub(crate) fn verify_idempotency(
+    path: Option<&Path>,
+    formatted: &str,
+    reformatted: &str,
+) -> std::result::Result<(), Error> {
+    let Some(offset) = formatted
+        .char_indices()
+        .zip(reformatted.chars())
+        .find(|((_, c0), c1)| c0 != c1)
+        .map(|((i, _), _)| i)
+        .or_else(|| {
+            (formatted.len() != reformatted.len()).then(|| formatted.len().min(reformatted.len()))
+        })
+    else {
+        return Ok(());
+    };
+    Err(Error::NotIdempotent {
+        path: path.map(|p| p.to_path_buf()),
+        position: crate::range::position_at(formatted, offset),
+        diff: crate::diff::text_diff(formatted, reformatted, path.unwrap_or(Path::new(FORMATTED))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_tokens_works() {
+        assert!(verify_tokens(None, "foo(A,B).", "foo(A, B).\n").is_ok());
+        assert!(verify_tokens(None, "%% comment  \nfoo.", "%% comment\nfoo.").is_ok());
+
+        let e = verify_tokens(None, "foo(A, B).", "foo(A, C).").unwrap_err();
+        assert!(matches!(
+            e,
+            Error::TokenMismatch {
+                original_position: Some(p0),
+                formatted_position: Some(p1),
+                ..
+            } if p0.offset() == 7 && p1.offset() == 7
+        ));
+
+        let e = verify_tokens(None, "foo.", "foo. bar").unwrap_err();
+        assert!(matches!(
+            e,
+            Error::TokenMismatch {
+                original_position: None,
+                formatted_position: Some(p1),
+                ..
+            } if p1.offset() == 5
+        ));
+
+        // Untokenizable forms kept as-is (error-tolerant mode).
+        let original = "foo() -> `a. \nbar()->bar.";
+        assert!(verify_tokens(None, original, "foo() -> `a. \n\n\nbar() -> bar.").is_ok());
+
+        let e = verify_tokens(None, original, "foo() -> `b. \n\n\nbar() -> bar.").unwrap_err();
+        assert!(matches!(e, Error::Tokenize(_)));
+        assert_eq!(e.position().map(|p| p.offset()), Some(9));
+    }
+
+    #[test]
+    fn verify_idempotency_works() {
+        assert!(verify_idempotency(None, "foo.\n", "foo.\n").is_ok());
+
+        let e = verify_idempotency(None, "foo.\nbar.\n", "foo.\nbar,\n").unwrap_err();
+        assert_eq!((e.line(), e.column()), (Some(2), Some(4)));
+
+        let e = verify_idempotency(None, "foo.\n", "foo.\n\n").unwrap_err();
+        assert_eq!((e.line(), e.column()), (Some(2), Some(1)));
+    }
+}